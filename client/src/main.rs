@@ -1,3 +1,139 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use common::bot::{Bot, BotDifficulty, Budget, IsmctsBot};
+use common::game_logic::{self, PlayedMove};
+use common::rules::{RuleSet, Variant};
+use common::{Board, Piece, PieceType, Side};
+use uuid::Uuid;
+
+/// Plays two bots against each other offline.
+///
+/// `client [red difficulty] [blue difficulty] [expert iterations] [expert seconds] [variant]`
 fn main() {
-    println!("Hello, world!");
+    let args = std::env::args().collect::<Vec<_>>();
+    let difficulty = |i: usize| {
+        args.get(i)
+            .map(|arg| BotDifficulty::from_str(arg).expect("unknown difficulty"))
+            .unwrap_or(BotDifficulty::Expert)
+    };
+    let budget = Budget {
+        iterations: args.get(3).map_or(1000, |arg| arg.parse().expect("invalid iterations")),
+        time: args
+            .get(4)
+            .map(|arg| Duration::from_secs_f32(arg.parse().expect("invalid seconds"))),
+    };
+    let bot = |difficulty: BotDifficulty| -> Box<dyn Bot> {
+        match difficulty {
+            BotDifficulty::Expert => Box::new(IsmctsBot {
+                budget: budget.clone(),
+                ..Default::default()
+            }),
+            difficulty => difficulty.bot(),
+        }
+    };
+
+    let mut bots = [(Side::Red, bot(difficulty(1))), (Side::Blue, bot(difficulty(2)))];
+
+    let rules = args
+        .get(5)
+        .map(|arg| Variant::from_str(arg).expect("unknown variant"))
+        .unwrap_or_default()
+        .rules();
+    let mut board = Board::new(rules.layout.clone());
+    for (side, bot) in bots.iter_mut() {
+        let pieces = bot.choose_setup(&rules, side);
+        let zone = rules.layout.setup_zone(side == &Side::Red).to_vec();
+        for ((x, y), piece_type) in zone.into_iter().zip(pieces) {
+            board.set(
+                x,
+                y,
+                piece_type.map(|piece_type| Piece {
+                    id: Uuid::new_v4(),
+                    owner: side.clone(),
+                    piece_type,
+                    knowledge: Default::default(),
+                }),
+            );
+        }
+    }
+    println!("{}", render(&board, &rules));
+
+    let mut history = Vec::<PlayedMove>::new();
+    let mut turn = 0;
+    let outcome = loop {
+        let (side, bot) = &mut bots[turn % 2];
+        if let Some(outcome) = game_logic::game_outcome(&board, &rules, &history, side) {
+            break outcome;
+        }
+
+        let view = board.view_for(Some(side));
+        let piece_move = bot
+            .choose_move(&view, &rules, side, &history)
+            .expect("bot found no move");
+        let id = board
+            .get(piece_move.from.0, piece_move.from.1)
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .id;
+        let response = game_logic::move_piece(&mut board, &rules, id, piece_move.to.0, piece_move.to.1)
+            .expect("bot made an illegal move");
+
+        println!(
+            "{} {}: {:?} -> {:?} {:?}",
+            turn + 1,
+            side,
+            piece_move.from,
+            piece_move.to,
+            response
+        );
+        history.push(PlayedMove {
+            side: side.clone(),
+            piece_move,
+            response,
+        });
+        turn += 1;
+    };
+
+    println!("{}", render(&board, &rules));
+    println!("{:?} after {} moves", outcome, turn);
+}
+
+fn render(board: &Board, rules: &RuleSet) -> String {
+    let mut out = String::new();
+    for y in 0..board.layout.height {
+        for x in 0..board.layout.width {
+            let square = match board.get(x, y).unwrap() {
+                Some(piece) => {
+                    let symbol = match piece.piece_type {
+                        PieceType::Bomb => 'B',
+                        PieceType::Flag => 'F',
+                        PieceType::Spy => 'S',
+                        PieceType::Unknown => '?',
+                        PieceType::Custom(_) => rules
+                            .piece(&piece.piece_type)
+                            .and_then(|piece| piece.name.chars().next())
+                            .unwrap_or('C')
+                            .to_ascii_uppercase(),
+                        ref piece_type => {
+                            let rank = rules.piece(piece_type).map_or(0, |piece| piece.rank);
+                            char::from_digit(11 - rank as u32, 10).unwrap_or('1')
+                        }
+                    };
+                    if piece.owner == Side::Red {
+                        symbol.to_ascii_lowercase()
+                    } else {
+                        symbol
+                    }
+                }
+                None if board.layout.is_impassable(x, y) => '~',
+                None => '.',
+            };
+            out.push(square);
+            out.push(' ');
+        }
+        out.push('\n');
+    }
+    out
 }
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::catalog::Movement;
+use crate::game_logic::{GameOutcome, Move, DIRECTIONS};
+use crate::layout::BoardLayout;
+use crate::rules::{Combat, RuleSet};
+use crate::{Board, Piece, PieceKnowledge, PieceType, Side};
+
+/// The most squares a `BitBoard` can hold.
+pub const MAX_SQUARES: usize = 128;
+
+/// A compact copy of a `Board` for search. Sets of squares are bitboards, bit `i` standing for
+/// the square kept at `i` in `Board::squares`, and what never changes about a piece is kept once
+/// in a table shared between copies.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BitBoard {
+    pub layout: Arc<BoardLayout>,
+    pub impassable: u128,
+    /// The squares taken by each side, Red first.
+    pub sides: [u128; 2],
+    /// The squares taken by each of `kinds`.
+    pub types: Vec<u128>,
+    /// The types on the board when it was converted.
+    pub kinds: Arc<Vec<PieceType>>,
+    pub revealed: u128,
+    pub moved: u128,
+    pub scouted: u128,
+    /// The entry of `pieces` standing on each square.
+    slots: [u8; MAX_SQUARES],
+    /// The id, owner and index into `kinds` of every piece.
+    pieces: Arc<Vec<(Uuid, Side, usize)>>,
+}
+
+fn side_index(side: &Side) -> usize {
+    match side {
+        Side::Red => 0,
+        Side::Blue => 1,
+    }
+}
+
+/// The squares in `set`, lowest first.
+pub fn squares(mut set: u128) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if set == 0 {
+            return None;
+        }
+        let square = set.trailing_zeros() as usize;
+        set &= set - 1;
+        Some(square)
+    })
+}
+
+impl From<&Board> for BitBoard {
+    /// Panics if `board` has more than `MAX_SQUARES` squares.
+    fn from(board: &Board) -> Self {
+        assert!(
+            board.squares.len() <= MAX_SQUARES,
+            "board too large for a BitBoard"
+        );
+        let layout = &board.layout;
+
+        let mut bitboard = BitBoard {
+            layout: layout.clone(),
+            impassable: 0,
+            sides: [0; 2],
+            types: Vec::new(),
+            kinds: Arc::default(),
+            revealed: 0,
+            moved: 0,
+            scouted: 0,
+            slots: [0; MAX_SQUARES],
+            pieces: Arc::default(),
+        };
+        for index in layout
+            .impassable
+            .iter()
+            .filter_map(|&(x, y)| layout.index(x, y))
+        {
+            bitboard.impassable |= 1 << index;
+        }
+
+        let mut kinds = Vec::new();
+        let mut pieces = Vec::new();
+        for (index, piece) in board.squares.iter().enumerate() {
+            let Some(piece) = piece else {
+                continue;
+            };
+            let kind = match kinds.iter().position(|kind| kind == &piece.piece_type) {
+                Some(kind) => kind,
+                None => {
+                    kinds.push(piece.piece_type.clone());
+                    bitboard.types.push(0);
+                    kinds.len() - 1
+                }
+            };
+
+            let bit = 1 << index;
+            bitboard.sides[side_index(&piece.owner)] |= bit;
+            bitboard.types[kind] |= bit;
+            if piece.knowledge.revealed {
+                bitboard.revealed |= bit;
+            }
+            if piece.knowledge.moved {
+                bitboard.moved |= bit;
+            }
+            if piece.knowledge.scouted {
+                bitboard.scouted |= bit;
+            }
+            bitboard.slots[index] = pieces.len() as u8;
+            pieces.push((piece.id, piece.owner.clone(), kind));
+        }
+
+        bitboard.kinds = Arc::new(kinds);
+        bitboard.pieces = Arc::new(pieces);
+        bitboard
+    }
+}
+
+impl BitBoard {
+    /// The same position as a `Board`, nothing being lost in the conversion.
+    pub fn to_board(&self) -> Board {
+        let mut board = Board::new((*self.layout).clone());
+        for index in squares(self.sides[0] | self.sides[1]) {
+            let (id, owner, kind) = &self.pieces[self.slots[index] as usize];
+            let bit = 1 << index;
+            let (x, y) = self.layout.square(index);
+            board.set(
+                x,
+                y,
+                Some(Piece {
+                    id: *id,
+                    owner: owner.clone(),
+                    piece_type: self.kinds[*kind].clone(),
+                    knowledge: PieceKnowledge {
+                        revealed: self.revealed & bit != 0,
+                        moved: self.moved & bit != 0,
+                        scouted: self.scouted & bit != 0,
+                    },
+                }),
+            );
+        }
+        board
+    }
+
+    fn kind_at(&self, index: usize) -> usize {
+        self.pieces[self.slots[index] as usize].2
+    }
+
+    /// How many pieces of `piece_type` `side` has.
+    pub fn count(&self, side: &Side, piece_type: &PieceType) -> u32 {
+        match self.kinds.iter().position(|kind| kind == piece_type) {
+            Some(kind) => (self.types[kind] & self.sides[side_index(side)]).count_ones(),
+            None => 0,
+        }
+    }
+
+    /// Every legal move of `side`: piece by piece from the top left square, the moves of each
+    /// piece in the order `game_logic::legal_moves_from` gives them.
+    pub fn legal_moves(&self, rules: &RuleSet, side: &Side) -> Vec<Move> {
+        let own = self.sides[side_index(side)];
+        let opponent = self.sides[1 - side_index(side)];
+        let (width, height) = (self.layout.width as isize, self.layout.height as isize);
+        let ranges = self
+            .kinds
+            .iter()
+            .map(|kind| match rules.movement(kind) {
+                Movement::Immovable => 0,
+                Movement::Step => 1,
+                Movement::Line => width.max(height),
+            })
+            .collect::<Vec<_>>();
+
+        let mut moves = Vec::new();
+        for from in squares(own) {
+            let range = ranges[self.kind_at(from)];
+            let (x, y) = self.layout.square(from);
+
+            for (dx, dy) in DIRECTIONS {
+                for step in 1..=range {
+                    let (i, j) = (x as isize + dx * step, y as isize + dy * step);
+                    if i < 0 || j < 0 || i >= width || j >= height {
+                        break;
+                    }
+                    let bit = 1 << (i + j * width);
+                    if (self.impassable | own) & bit != 0 {
+                        break;
+                    }
+
+                    let piece_move = Move {
+                        from: (x, y),
+                        to: (i as usize, j as usize),
+                    };
+                    if opponent & bit != 0 {
+                        if step == 1 || rules.scout_move_and_attack {
+                            moves.push(piece_move);
+                        }
+                        break;
+                    }
+                    moves.push(piece_move);
+                }
+            }
+        }
+        moves
+    }
+
+    fn remove(&mut self, index: usize, side: usize, kind: usize) {
+        let mask = !(1 << index);
+        self.sides[side] &= mask;
+        self.types[kind] &= mask;
+        self.revealed &= mask;
+        self.moved &= mask;
+        self.scouted &= mask;
+    }
+
+    /// Makes `piece_move`, which has to be legal, the way `MoveRecord::apply` would.
+    pub fn play(&mut self, rules: &RuleSet, piece_move: &Move) {
+        let from = self
+            .layout
+            .index(piece_move.from.0, piece_move.from.1)
+            .unwrap();
+        let to = self.layout.index(piece_move.to.0, piece_move.to.1).unwrap();
+        let (from_bit, to_bit) = (1 << from, 1 << to);
+
+        let slot = self.slots[from];
+        let side = side_index(&self.pieces[slot as usize].1);
+        let kind = self.kind_at(from);
+        let mut revealed = self.revealed & from_bit != 0;
+        let distance = piece_move.from.0.abs_diff(piece_move.to.0)
+            + piece_move.from.1.abs_diff(piece_move.to.1);
+        let scouted = self.scouted & from_bit != 0 || distance > 1;
+        self.remove(from, side, kind);
+
+        if self.sides[1 - side] & to_bit != 0 {
+            let defender = self.kind_at(to);
+            match rules.resolve(&self.kinds[kind], &self.kinds[defender]) {
+                Combat::AttackerWins => {
+                    self.remove(to, 1 - side, defender);
+                    revealed = true;
+                }
+                Combat::DefenderWins => {
+                    self.revealed |= to_bit;
+                    return;
+                }
+                Combat::BothLose => {
+                    self.remove(to, 1 - side, defender);
+                    return;
+                }
+            }
+        }
+
+        self.sides[side] |= to_bit;
+        self.types[kind] |= to_bit;
+        self.moved |= to_bit;
+        if revealed {
+            self.revealed |= to_bit;
+        }
+        if scouted {
+            self.scouted |= to_bit;
+        }
+        self.slots[to] = slot;
+    }
+
+    /// Like `game_logic::game_outcome`, leaving out the repetition rules.
+    pub fn outcome(&self, rules: &RuleSet, side_to_move: &Side) -> Option<GameOutcome> {
+        if self.count(side_to_move, &PieceType::Flag) == 0 {
+            return Some(GameOutcome::FlagCaptured(!side_to_move.clone()));
+        }
+        if self.count(!side_to_move, &PieceType::Flag) == 0 {
+            return Some(GameOutcome::FlagCaptured(side_to_move.clone()));
+        }
+
+        if self.legal_moves(rules, side_to_move).is_empty() {
+            if !self.legal_moves(rules, !side_to_move).is_empty() {
+                return Some(GameOutcome::NoMovablePieces(!side_to_move.clone()));
+            }
+            return Some(GameOutcome::Draw);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::tests::{sample_positions, squares};
+    use crate::game_logic::{legal_moves_from, MoveRecord};
+
+    #[test]
+    fn converts_losslessly() {
+        for (board, _) in sample_positions() {
+            assert_eq!(BitBoard::from(&board).to_board(), board);
+        }
+    }
+
+    #[test]
+    fn legal_moves_agree_with_legal_moves_from() {
+        for (board, rules) in sample_positions() {
+            for side in [Side::Red, Side::Blue] {
+                let expected = squares(&board)
+                    .filter(|&(x, y)| {
+                        matches!(board.get(x, y), Some(Some(piece)) if piece.owner == side)
+                    })
+                    .flat_map(|(x, y)| legal_moves_from(&board, &rules, x, y))
+                    .collect::<Vec<_>>();
+                assert_eq!(BitBoard::from(&board).legal_moves(&rules, &side), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn play_agrees_with_move_record() {
+        for (board, rules) in sample_positions() {
+            let bitboard = BitBoard::from(&board);
+            for side in [Side::Red, Side::Blue] {
+                for piece_move in bitboard.legal_moves(&rules, &side) {
+                    let mut played = bitboard.clone();
+                    played.play(&rules, &piece_move);
+                    let mut applied = board.clone();
+                    MoveRecord::new(&board, &rules, &piece_move)
+                        .unwrap()
+                        .apply(&mut applied);
+                    assert_eq!(played.to_board(), applied, "{:?}", piece_move);
+                }
+            }
+        }
+    }
+}
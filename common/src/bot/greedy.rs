@@ -0,0 +1,56 @@
+use rand::Rng;
+
+use super::{attack_value, random_setup, Bot};
+use crate::game_logic::{self, Move, PlayedMove};
+use crate::inference;
+use crate::rules::RuleSet;
+use crate::{Board, PieceType, Side};
+
+/// Takes whichever move wins the most material right away, weighing attacks on hidden pieces by
+/// what they are likely to be.
+pub struct GreedyBot;
+
+impl Bot for GreedyBot {
+    fn choose_setup(&mut self, rules: &RuleSet, _side: &Side) -> Vec<Option<PieceType>> {
+        random_setup(rules)
+    }
+
+    fn choose_move(
+        &mut self,
+        view: &Board,
+        rules: &RuleSet,
+        side: &Side,
+        history: &[PlayedMove],
+    ) -> Option<Move> {
+        let mut rng = rand::thread_rng();
+        let beliefs = inference::infer(view, rules, side, history);
+
+        game_logic::allowed_moves(view, rules, history, side)
+            .map(|piece_move| {
+                let attacker = view
+                    .get(piece_move.from.0, piece_move.from.1)
+                    .unwrap()
+                    .as_ref()
+                    .unwrap();
+                let value = match view.get(piece_move.to.0, piece_move.to.1).unwrap() {
+                    None => 0.0,
+                    Some(defender) if defender.piece_type != PieceType::Unknown => {
+                        attack_value(rules, &attacker.piece_type, &defender.piece_type)
+                    }
+                    Some(defender) => beliefs
+                        .get(&defender.id)
+                        .map(|distribution| {
+                            distribution
+                                .iter()
+                                .map(|(t, p)| attack_value(rules, &attacker.piece_type, t) * p)
+                                .sum()
+                        })
+                        .unwrap_or(0.0),
+                };
+                // break ties randomly
+                (piece_move, value + rng.gen_range(0.0..0.01))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(piece_move, _)| piece_move)
+    }
+}
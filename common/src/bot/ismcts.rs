@@ -0,0 +1,218 @@
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+
+use super::{determinize, material, random_setup, searched_history, Bot};
+use crate::bitboard::BitBoard;
+use crate::game_logic::{self, Move, PlayedMove};
+use crate::inference::remaining_counts;
+use crate::rules::RuleSet;
+use crate::{Board, PieceType, Side};
+
+/// How long an `IsmctsBot` thinks about a move. The search stops at whichever limit comes first.
+#[derive(Clone, Debug)]
+pub struct Budget {
+    pub iterations: usize,
+    /// Needs a platform with a clock, so leave it unset in the browser.
+    pub time: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            iterations: 1000,
+            time: None,
+        }
+    }
+}
+
+/// Information set Monte Carlo tree search. Each iteration guesses the hidden pieces anew, so the
+/// tree is shared between every board the opponent could have, and only moves that were legal on
+/// a guess are counted as having been available.
+pub struct IsmctsBot {
+    pub budget: Budget,
+    /// How many plies a playout runs before the position is scored on material.
+    pub playout_depth: usize,
+    pub exploration: f32,
+}
+
+impl Default for IsmctsBot {
+    fn default() -> Self {
+        Self {
+            budget: Budget::default(),
+            playout_depth: 20,
+            exploration: 0.7,
+        }
+    }
+}
+
+struct Node {
+    piece_move: Option<Move>,
+    /// The side that made `piece_move`.
+    side: Side,
+    children: Vec<usize>,
+    visits: f32,
+    available: f32,
+    reward: f32,
+}
+
+impl Bot for IsmctsBot {
+    fn choose_setup(&mut self, rules: &RuleSet, _side: &Side) -> Vec<Option<PieceType>> {
+        random_setup(rules)
+    }
+
+    fn choose_move(
+        &mut self,
+        view: &Board,
+        rules: &RuleSet,
+        side: &Side,
+        history: &[PlayedMove],
+    ) -> Option<Move> {
+        let deadline = self.budget.time.map(|time| Instant::now() + time);
+        let pool = remaining_counts(view, rules, side, history);
+
+        let mut nodes = vec![Node {
+            piece_move: None,
+            side: !side.clone(),
+            children: Vec::new(),
+            visits: 0.0,
+            available: 0.0,
+            reward: 0.0,
+        }];
+
+        for _ in 0..self.budget.iterations {
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                break;
+            }
+            self.iterate(
+                &mut nodes,
+                determinize(view, rules, side, &pool),
+                rules,
+                side,
+                history,
+            );
+        }
+
+        nodes[0]
+            .children
+            .iter()
+            .max_by(|a, b| nodes[**a].visits.total_cmp(&nodes[**b].visits))
+            .and_then(|&child| nodes[child].piece_move)
+    }
+}
+
+impl IsmctsBot {
+    fn iterate(
+        &self,
+        nodes: &mut Vec<Node>,
+        view: Board,
+        rules: &RuleSet,
+        side: &Side,
+        history: &[PlayedMove],
+    ) {
+        let mut rng = rand::thread_rng();
+        let mut path = vec![0];
+        let mut to_move = side.clone();
+        let mut board = BitBoard::from(&view);
+
+        // selection and expansion
+        loop {
+            let past = searched_history(history, path.len() - 1);
+            // without a history to check, the bitboard gives the same answers faster; otherwise
+            // this is the root, where `view` is still the position
+            let legal = if past.is_empty() {
+                if board.outcome(rules, &to_move).is_some() {
+                    break;
+                }
+                board.legal_moves(rules, &to_move)
+            } else {
+                if game_logic::game_outcome(&view, rules, past, &to_move).is_some() {
+                    break;
+                }
+                game_logic::allowed_moves(&view, rules, past, &to_move).collect()
+            };
+            let node = *path.last().unwrap();
+
+            let mut untried = Vec::new();
+            for piece_move in &legal {
+                match nodes[node]
+                    .children
+                    .iter()
+                    .find(|&&child| nodes[child].piece_move.as_ref() == Some(piece_move))
+                {
+                    Some(&child) => nodes[child].available += 1.0,
+                    None => untried.push(*piece_move),
+                }
+            }
+
+            if let Some(piece_move) = untried.choose(&mut rng) {
+                let child = nodes.len();
+                nodes.push(Node {
+                    piece_move: Some(*piece_move),
+                    side: to_move.clone(),
+                    children: Vec::new(),
+                    visits: 0.0,
+                    available: 1.0,
+                    reward: 0.0,
+                });
+                nodes[node].children.push(child);
+                board.play(rules, piece_move);
+                path.push(child);
+                to_move = !to_move;
+                break;
+            }
+
+            let child = nodes[node]
+                .children
+                .iter()
+                .copied()
+                .filter(|&child| legal.contains(nodes[child].piece_move.as_ref().unwrap()))
+                .max_by(|&a, &b| self.ucb(&nodes[a]).total_cmp(&self.ucb(&nodes[b])));
+            let Some(child) = child else {
+                break;
+            };
+            board.play(rules, nodes[child].piece_move.as_ref().unwrap());
+            path.push(child);
+            to_move = !to_move;
+        }
+
+        let result = self.playout(board, rules, to_move, side);
+
+        for node in path {
+            let node = &mut nodes[node];
+            node.visits += 1.0;
+            node.reward += if &node.side == side {
+                result
+            } else {
+                1.0 - result
+            };
+        }
+    }
+
+    fn ucb(&self, node: &Node) -> f32 {
+        node.reward / node.visits + self.exploration * (node.available.ln() / node.visits).sqrt()
+    }
+
+    /// Plays random moves from `board` and scores the result for `side` between 0 and 1.
+    fn playout(&self, mut board: BitBoard, rules: &RuleSet, mut to_move: Side, side: &Side) -> f32 {
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..self.playout_depth {
+            if let Some(outcome) = board.outcome(rules, &to_move) {
+                return match outcome.winner() {
+                    Some(winner) if winner == side => 1.0,
+                    Some(_) => 0.0,
+                    None => 0.5,
+                };
+            }
+            let moves = board.legal_moves(rules, &to_move);
+            let Some(piece_move) = moves.choose(&mut rng) else {
+                break;
+            };
+            board.play(rules, piece_move);
+            to_move = !to_move;
+        }
+
+        1.0 / (1.0 + (-material(&board, rules, side) / 20.0).exp())
+    }
+}
@@ -0,0 +1,245 @@
+mod greedy;
+mod ismcts;
+mod random;
+mod search;
+
+pub use greedy::GreedyBot;
+pub use ismcts::{Budget, IsmctsBot};
+pub use random::RandomBot;
+pub use search::SearchBot;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, EnumString};
+
+use crate::bitboard::BitBoard;
+use crate::catalog::Movement;
+use crate::game_logic::{Move, PlayedMove};
+use crate::rules::{Combat, RuleSet};
+use crate::{Board, PieceType, Side};
+
+/// A computer player. Bots only ever see the board the way their own side does.
+pub trait Bot: Send {
+    fn choose_setup(&mut self, rules: &RuleSet, side: &Side) -> Vec<Option<PieceType>>;
+    /// Picks the next move for `side`, `view` being the board as `side` sees it.
+    fn choose_move(
+        &mut self,
+        view: &Board,
+        rules: &RuleSet,
+        side: &Side,
+        history: &[PlayedMove],
+    ) -> Option<Move>;
+}
+
+#[derive(Clone, PartialEq, Deserialize, Serialize, Debug, Default, Display, EnumIter, EnumString)]
+pub enum BotDifficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+    Expert,
+}
+
+impl BotDifficulty {
+    pub fn bot(&self) -> Box<dyn Bot> {
+        match self {
+            BotDifficulty::Easy => Box::new(RandomBot),
+            BotDifficulty::Medium => Box::new(GreedyBot),
+            BotDifficulty::Hard => Box::new(SearchBot::default()),
+            BotDifficulty::Expert => Box::new(IsmctsBot {
+                budget: Budget {
+                    iterations: 5000,
+                    time: Some(Duration::from_secs(3)),
+                },
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// A shuffled setup with the flag somewhere on the back row.
+pub fn random_setup(rules: &RuleSet) -> Vec<Option<PieceType>> {
+    let mut rng = rand::thread_rng();
+
+    let mut setup = rules
+        .pieces
+        .iter()
+        .flat_map(|piece| vec![Some(piece.piece_type.clone()); piece.count])
+        .collect::<Vec<_>>();
+    setup.resize(rules.layout.setup_zone(true).len(), None);
+    setup.shuffle(&mut rng);
+
+    // the back row is as far from the middle of the board as the zone goes
+    let zone = rules.layout.setup_zone(true);
+    let distance = |&(_, y): &(usize, usize)| y.abs_diff(rules.layout.height / 2);
+    let back = zone.iter().map(distance).max().unwrap_or(0);
+    let back_row = (0..zone.len())
+        .filter(|&i| distance(&zone[i]) == back)
+        .collect::<Vec<_>>();
+    if let Some(flag) = setup.iter().position(|piece| piece == &Some(PieceType::Flag)) {
+        setup.swap(flag, *back_row.choose(&mut rng).unwrap());
+    }
+    setup
+}
+
+/// Custom pieces are valued by their rank in the catalog of `rules`.
+pub fn piece_value(rules: &RuleSet, piece_type: &PieceType) -> f32 {
+    match piece_type {
+        PieceType::Flag => 1000.0,
+        PieceType::Marshal => 20.0,
+        PieceType::General => 15.0,
+        PieceType::Colonel => 10.0,
+        PieceType::Major => 8.0,
+        PieceType::Captain => 6.0,
+        PieceType::Lieutenant => 4.5,
+        PieceType::Sergeant => 3.0,
+        PieceType::Miner => 5.0,
+        PieceType::Scout => 2.5,
+        PieceType::Spy => 7.0,
+        PieceType::Bomb => 4.0,
+        PieceType::Unknown => 5.0,
+        PieceType::Custom(_) => rules
+            .piece(piece_type)
+            .map_or(5.0, |piece| 1.0 + piece.rank as f32 * 1.5),
+    }
+}
+
+/// The value of `side`'s pieces minus the value of its opponent's.
+pub fn material(board: &BitBoard, rules: &RuleSet, side: &Side) -> f32 {
+    board
+        .kinds
+        .iter()
+        .map(|piece_type| {
+            let own = board.count(side, piece_type) as f32;
+            let opposing = board.count(!side, piece_type) as f32;
+            piece_value(rules, piece_type) * (own - opposing)
+        })
+        .sum()
+}
+
+/// How much material an attack gains the attacker, counting what it loses as negative.
+pub fn attack_value(rules: &RuleSet, attacker: &PieceType, defender: &PieceType) -> f32 {
+    match rules.resolve(attacker, defender) {
+        Combat::AttackerWins => piece_value(rules, defender),
+        Combat::DefenderWins => -piece_value(rules, attacker),
+        Combat::BothLose => piece_value(rules, defender) - piece_value(rules, attacker),
+    }
+}
+
+/// The part of `history` the repetition rules are checked against `depth` plies into a search,
+/// `depth` 0 being the position the bot chooses a move in. Only that move is held to the rules:
+/// further down the history would have to grow with every line searched, so positions there,
+/// like those `BitBoard::outcome` scores, leave the rules out.
+pub fn searched_history(history: &[PlayedMove], depth: usize) -> &[PlayedMove] {
+    if depth == 0 {
+        history
+    } else {
+        &[]
+    }
+}
+
+/// Fills in every hidden opposing piece of `view` with a type it could have, keeping scouted
+/// pieces line movers and moved pieces movable, and making sure a flag is placed.
+pub fn determinize(
+    view: &Board,
+    rules: &RuleSet,
+    side: &Side,
+    pool: &HashMap<PieceType, usize>,
+) -> Board {
+    let mut rng = rand::thread_rng();
+    let mut board = view.clone();
+
+    let mut pool = pool
+        .iter()
+        .flat_map(|(piece_type, count)| vec![piece_type.clone(); *count])
+        .collect::<Vec<_>>();
+    pool.shuffle(&mut rng);
+
+    let mut hidden = (0..board.squares.len())
+        .filter(|&i| match &board.squares[i] {
+            Some(piece) => &piece.owner != side && piece.piece_type == PieceType::Unknown,
+            None => false,
+        })
+        .collect::<Vec<_>>();
+    hidden.shuffle(&mut rng);
+    // the most constrained pieces pick first, scouted ones before the ones that only moved
+    let knowledge = |i: usize| &board.squares[i].as_ref().unwrap().knowledge;
+    hidden.sort_by_key(|&i| (!knowledge(i).scouted, !knowledge(i).moved));
+
+    let fits = |i: usize, piece_type: &PieceType| {
+        let movement = rules.movement(piece_type);
+        if knowledge(i).scouted {
+            movement == Movement::Line
+        } else {
+            !knowledge(i).moved || movement != Movement::Immovable
+        }
+    };
+
+    let mut assignments = Vec::new();
+    for &i in &hidden {
+        let piece_type = match pool.iter().position(|piece_type| fits(i, piece_type)) {
+            Some(position) => pool.swap_remove(position),
+            None => PieceType::Unknown,
+        };
+        assignments.push((i, piece_type));
+    }
+
+    // the flag can get left in the pool when the history is incomplete, so it trades places
+    // with whatever an unmoved piece got
+    if let Some(flag) = pool
+        .iter()
+        .position(|piece_type| piece_type == &PieceType::Flag)
+    {
+        if let Some(assignment) = assignments.iter_mut().find(|(i, _)| !knowledge(*i).moved) {
+            std::mem::swap(&mut assignment.1, &mut pool[flag]);
+        }
+    }
+
+    for (i, piece_type) in assignments {
+        board.squares[i].as_mut().unwrap().piece_type = piece_type;
+    }
+    board.rehash();
+    board
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::tests::board;
+    use crate::rules::Variant;
+
+    #[test]
+    fn determinize_keeps_knowledge_and_counts() {
+        let rules = Variant::Classic.rules();
+        let mut view = board(&[
+            ((0, 9), Side::Blue, PieceType::Unknown),
+            ((1, 9), Side::Blue, PieceType::Unknown),
+            ((2, 9), Side::Blue, PieceType::Unknown),
+        ]);
+        let index = |x, y| view.layout.index(x, y).unwrap();
+        let (scouted, moved, unmoved) = (index(0, 9), index(1, 9), index(2, 9));
+        let knowledge = &mut view.squares[scouted].as_mut().unwrap().knowledge;
+        knowledge.moved = true;
+        knowledge.scouted = true;
+        view.squares[moved].as_mut().unwrap().knowledge.moved = true;
+        let pool = [
+            (PieceType::Scout, 1),
+            (PieceType::Marshal, 1),
+            (PieceType::Bomb, 1),
+            (PieceType::Flag, 1),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        for _ in 0..20 {
+            let board = determinize(&view, &rules, &Side::Red, &pool);
+            let piece_type = |i: usize| board.squares[i].as_ref().unwrap().piece_type.clone();
+            assert_eq!(piece_type(scouted), PieceType::Scout);
+            assert_eq!(piece_type(moved), PieceType::Marshal);
+            assert_eq!(piece_type(unmoved), PieceType::Flag);
+        }
+    }
+}
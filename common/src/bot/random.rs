@@ -0,0 +1,28 @@
+use rand::seq::SliceRandom;
+
+use super::{random_setup, Bot};
+use crate::game_logic::{self, Move, PlayedMove};
+use crate::rules::RuleSet;
+use crate::{Board, PieceType, Side};
+
+/// Plays any legal move.
+pub struct RandomBot;
+
+impl Bot for RandomBot {
+    fn choose_setup(&mut self, rules: &RuleSet, _side: &Side) -> Vec<Option<PieceType>> {
+        random_setup(rules)
+    }
+
+    fn choose_move(
+        &mut self,
+        view: &Board,
+        rules: &RuleSet,
+        side: &Side,
+        history: &[PlayedMove],
+    ) -> Option<Move> {
+        game_logic::allowed_moves(view, rules, history, side)
+            .collect::<Vec<_>>()
+            .choose(&mut rand::thread_rng())
+            .copied()
+    }
+}
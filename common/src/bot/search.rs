@@ -0,0 +1,70 @@
+use super::{determinize, material, random_setup, searched_history, Bot};
+use crate::bitboard::BitBoard;
+use crate::game_logic::{self, Move, PlayedMove};
+use crate::inference::remaining_counts;
+use crate::rules::RuleSet;
+use crate::{Board, PieceType, Side};
+
+/// Guesses the hidden pieces a number of times and looks two plies ahead on each guess,
+/// playing the move that does best on average.
+pub struct SearchBot {
+    pub samples: usize,
+}
+
+impl Default for SearchBot {
+    fn default() -> Self {
+        Self { samples: 8 }
+    }
+}
+
+impl Bot for SearchBot {
+    fn choose_setup(&mut self, rules: &RuleSet, _side: &Side) -> Vec<Option<PieceType>> {
+        random_setup(rules)
+    }
+
+    fn choose_move(
+        &mut self,
+        view: &Board,
+        rules: &RuleSet,
+        side: &Side,
+        history: &[PlayedMove],
+    ) -> Option<Move> {
+        let past = searched_history(history, 0);
+        let moves = game_logic::allowed_moves(view, rules, past, side).collect::<Vec<_>>();
+        let pool = remaining_counts(view, rules, side, history);
+        let mut scores = vec![0.0; moves.len()];
+
+        for _ in 0..self.samples {
+            let board = BitBoard::from(&determinize(view, rules, side, &pool));
+            for (piece_move, score) in moves.iter().zip(scores.iter_mut()) {
+                *score += reply_value(&board, rules, side, piece_move);
+            }
+        }
+
+        moves
+            .into_iter()
+            .zip(scores)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(piece_move, _)| piece_move)
+    }
+}
+
+/// The material of `side` after `piece_move` and the opponent's strongest answer to it.
+fn reply_value(board: &BitBoard, rules: &RuleSet, side: &Side, piece_move: &Move) -> f32 {
+    let mut board = board.clone();
+    board.play(rules, piece_move);
+    if board.outcome(rules, !side).is_some() {
+        return material(&board, rules, side);
+    }
+
+    board
+        .legal_moves(rules, !side)
+        .iter()
+        .map(|reply| {
+            let mut board = board.clone();
+            board.play(rules, reply);
+            material(&board, rules, side)
+        })
+        .min_by(f32::total_cmp)
+        .unwrap_or_else(|| material(&board, rules, side))
+}
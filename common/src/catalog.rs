@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::rules::Combat;
+use crate::PieceType;
+
+/// How a piece gets around the board.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Movement {
+    Immovable,
+    /// One square at a time.
+    #[default]
+    Step,
+    /// Any number of free squares in a straight line, like a Scout.
+    Line,
+}
+
+/// One type of piece a variant is played with.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct PieceDefinition {
+    pub piece_type: PieceType,
+    pub name: String,
+    /// Higher ranks beat lower ones, equal ranks are a tie.
+    pub rank: u8,
+    pub movement: Movement,
+    /// How its attacks on these types end instead, like the Miner clearing a Bomb.
+    #[serde(default)]
+    pub attacks: Vec<(PieceType, Combat)>,
+    /// The picture drawn for it, `static/assets/temp/<side> <asset>.webp` on the web.
+    pub asset: String,
+    /// How many every side starts with.
+    pub count: usize,
+}
+
+impl PieceDefinition {
+    /// The classic definition of `piece_type`, with `count` of it.
+    pub fn classic(piece_type: PieceType, count: usize) -> Self {
+        let rank = match piece_type {
+            PieceType::Bomb => 11,
+            PieceType::Marshal => 10,
+            PieceType::General => 9,
+            PieceType::Colonel => 8,
+            PieceType::Major => 7,
+            PieceType::Captain => 6,
+            PieceType::Lieutenant => 5,
+            PieceType::Sergeant => 4,
+            PieceType::Miner => 3,
+            PieceType::Scout => 2,
+            PieceType::Spy => 1,
+            PieceType::Flag | PieceType::Unknown | PieceType::Custom(_) => 0,
+        };
+        let movement = match piece_type {
+            PieceType::Bomb | PieceType::Flag => Movement::Immovable,
+            PieceType::Scout => Movement::Line,
+            _ => Movement::Step,
+        };
+        let attacks = match piece_type {
+            PieceType::Miner => vec![(PieceType::Bomb, Combat::AttackerWins)],
+            PieceType::Spy => vec![(PieceType::Marshal, Combat::AttackerWins)],
+            _ => vec![],
+        };
+
+        Self {
+            name: piece_type.to_string(),
+            asset: piece_type.to_string().to_lowercase(),
+            piece_type,
+            rank,
+            movement,
+            attacks,
+            count,
+        }
+    }
+}
+
+/// The forty pieces of classic Stratego.
+pub fn classic() -> Vec<PieceDefinition> {
+    PieceType::iter()
+        .filter(|piece_type| piece_type.starting_count() > 0)
+        .map(|piece_type| {
+            let count = piece_type.starting_count();
+            PieceDefinition::classic(piece_type, count)
+        })
+        .collect()
+}
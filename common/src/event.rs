@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::game_logic::{GameOutcome, MoveError, PlayedMove};
+use crate::{Board, BoardState, GamePhase, Side};
+
+/// What a game channel pushes to its clients. Boards are as the receiving client gets to see
+/// them.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum GameEvent {
+    /// The whole game, sent first when a client connects.
+    State(BoardState),
+    /// A player took the seat of this side.
+    OpponentJoined(Side),
+    /// This side placed its pieces, leaving the board and the phase as they are now.
+    SetupDone {
+        side: Side,
+        board: Board,
+        phase: GamePhase,
+    },
+    /// A move and how its combat ended, leaving the board as it is now.
+    MoveMade {
+        played: PlayedMove,
+        board: Board,
+    },
+    GameOver(GameOutcome),
+    /// A message from a player, or from a spectator without a side.
+    Chat {
+        from: Option<Side>,
+        text: String,
+    },
+    /// A move this client sent was not played. Only sent to that client.
+    MoveRejected(MoveError),
+}
+
+/// What a client sends over its game channel, acting with the token it connected with.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum ClientMessage {
+    Move { piece_id: Uuid, x: usize, y: usize },
+    Chat(String),
+}
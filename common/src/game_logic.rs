@@ -1,14 +1,30 @@
-use crate::{Board, Piece, PieceType};
+use crate::catalog::Movement;
+use crate::rules::{Combat, RuleSet};
+use crate::{Board, GamePhase, Piece, PieceType, Side};
 use serde::{Deserialize, Serialize};
+use strum::Display;
 use thiserror::Error;
 use uuid::Uuid;
 
-pub fn valid_move_from_id(board: &Board, id: Uuid, x: usize, y: usize) -> MoveResult {
+pub fn valid_move_from_id(
+    board: &Board,
+    rules: &RuleSet,
+    id: Uuid,
+    x: usize,
+    y: usize,
+) -> MoveResult {
     let piece_position = board.find(id).ok_or(MoveError::PieceDoesNotExist(id))?;
-    valid_move(board, piece_position.0, piece_position.1, x, y)
+    valid_move(board, rules, piece_position.0, piece_position.1, x, y)
 }
 
-pub fn valid_move(board: &Board, u: usize, v: usize, x: usize, y: usize) -> MoveResult {
+pub fn valid_move(
+    board: &Board,
+    rules: &RuleSet,
+    u: usize,
+    v: usize,
+    x: usize,
+    y: usize,
+) -> MoveResult {
     let piece_position = (u, v);
     let piece = board
         .get(piece_position.0, piece_position.1)
@@ -17,10 +33,8 @@ pub fn valid_move(board: &Board, u: usize, v: usize, x: usize, y: usize) -> Move
         .ok_or(MoveError::PieceNotFound(u, v))?;
 
     //immovables
-    match &piece.piece_type {
-        PieceType::Bomb => Err(MoveError::Immovable)?,
-        PieceType::Flag => Err(MoveError::Immovable)?,
-        _ => {}
+    if rules.movement(&piece.piece_type) == Movement::Immovable {
+        Err(MoveError::Immovable)?;
     }
 
     //same position
@@ -29,102 +43,49 @@ pub fn valid_move(board: &Board, u: usize, v: usize, x: usize, y: usize) -> Move
     }
 
     //water
-    if match (x, y) {
-        //left
-        (2, 4) => true,
-        (3, 4) => true,
-        (2, 5) => true,
-        (3, 5) => true,
-        //right
-        (6, 4) => true,
-        (7, 4) => true,
-        (6, 5) => true,
-        (7, 5) => true,
-        _ => false,
-    } {
+    if board.layout.is_impassable(x, y) {
         return Err(MoveError::InvalidLocation);
     }
 
     //grid constraints
-    if piece.piece_type != PieceType::Scout {
-        if !((piece_position.0 < 9 && (x == piece_position.0 + 1 && y == piece_position.1))
-            || (piece_position.0 > 0 && (x == piece_position.0 - 1 && y == piece_position.1))
-            || (piece_position.1 < 9 && (x == piece_position.0 && y == piece_position.1 + 1))
-            || (piece_position.1 > 0 && (x == piece_position.0 && y == piece_position.1 - 1)))
-        {
-            Err(MoveError::OutsideOfMoveRange(x, y))?;
-        }
-    } else {
-        if !(piece_position.0 == x || piece_position.1 == y) {
+    if !board.layout.contains(x, y) || (x != piece_position.0 && y != piece_position.1) {
+        Err(MoveError::OutsideOfMoveRange(x, y))?;
+    }
+    let distance = piece_position.0.abs_diff(x) + piece_position.1.abs_diff(y);
+    if distance > move_range(board, rules, &piece.piece_type) {
+        Err(MoveError::OutsideOfMoveRange(x, y))?;
+    }
+    //nothing may be jumped over
+    let direction = (
+        (x as isize - piece_position.0 as isize).signum(),
+        (y as isize - piece_position.1 as isize).signum(),
+    );
+    for step in 1..distance as isize {
+        let (i, j) = (
+            (piece_position.0 as isize + direction.0 * step) as usize,
+            (piece_position.1 as isize + direction.1 * step) as usize,
+        );
+        if board.layout.is_impassable(i, j) || board.get(i, j).unwrap().is_some() {
             Err(MoveError::OutsideOfMoveRange(x, y))?;
         }
-        //scout constraints
-        if piece_position.0 == x {
-            if piece_position.1 > y {
-                let mut max_move = 0;
-                for i in piece_position.1 - 1..0 {
-                    if board.get(x, i).unwrap().is_some() {
-                        max_move = i;
-                        break;
-                    }
-                }
-                if y < max_move {
-                    Err(MoveError::OutsideOfMoveRange(x, y))?;
-                }
-            } else {
-                let mut max_move = 9;
-                for i in piece_position.1 + 1..10 {
-                    if board.get(x, i).unwrap().is_some() {
-                        max_move = i;
-                        break;
-                    }
-                }
-                if y > max_move {
-                    Err(MoveError::OutsideOfMoveRange(x, y))?;
-                }
-            }
-        } else {
-            if piece_position.0 > x {
-                let mut max_move = 0;
-                for i in piece_position.0 - 1..0 {
-                    if board.get(i, y).unwrap().is_some() {
-                        max_move = i;
-                        break;
-                    }
-                }
-                if x < max_move {
-                    Err(MoveError::OutsideOfMoveRange(x, y))?;
-                }
-            } else {
-                let mut max_move = 9;
-                for i in piece_position.0 + 1..10 {
-                    if board.get(i, y).unwrap().is_some() {
-                        max_move = i;
-                        break;
-                    }
-                }
-                if y > max_move {
-                    Err(MoveError::OutsideOfMoveRange(x, y))?;
-                }
-            }
-        }
     }
 
     // attack testing
 
     if let Some(other_piece) = board.get(x, y).unwrap() {
         if piece.owner != other_piece.owner {
-            if piece.piece_type == other_piece.piece_type {
-                return Ok(MoveResponse::AttackFailureMutual(
-                    other_piece.clone(),
-                    piece,
-                ));
+            if distance > 1 && !rules.scout_move_and_attack {
+                return Err(MoveError::OutsideOfMoveRange(x, y));
             }
-
-            if piece.piece_type.triumphs(&other_piece.piece_type) {
-                return Ok(MoveResponse::AttackSuccess(other_piece.clone()));
-            }
-            return Ok(MoveResponse::AttackFailure(piece));
+            return Ok(
+                match rules.resolve(&piece.piece_type, &other_piece.piece_type) {
+                    Combat::AttackerWins => MoveResponse::AttackSuccess(other_piece.clone()),
+                    Combat::DefenderWins => MoveResponse::AttackFailure(piece),
+                    Combat::BothLose => {
+                        MoveResponse::AttackFailureMutual(other_piece.clone(), piece)
+                    }
+                },
+            );
         } else {
             return Err(MoveError::FriendlyFire);
         }
@@ -133,9 +94,183 @@ pub fn valid_move(board: &Board, u: usize, v: usize, x: usize, y: usize) -> Move
     Ok(MoveResponse::Success)
 }
 
+/// How many squares a piece may move in a straight line.
+pub fn move_range(board: &Board, rules: &RuleSet, piece_type: &PieceType) -> usize {
+    match rules.movement(piece_type) {
+        Movement::Immovable => 0,
+        Movement::Step => 1,
+        Movement::Line => board.layout.width.max(board.layout.height),
+    }
+}
+
+pub(crate) const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+/// Every legal move of the piece at (`x`, `y`). Moves run outwards along each direction until
+/// they hit an impassable square, the edge of the board or another piece, which they may attack
+/// if it is an opponent's and `rules` allow attacking from that far.
+pub fn legal_moves_from<'a>(
+    board: &'a Board,
+    rules: &'a RuleSet,
+    x: usize,
+    y: usize,
+) -> impl Iterator<Item = Move> + 'a {
+    let piece = board.get(x, y).and_then(|piece| piece.as_ref());
+    let range = piece.map_or(0, |piece| move_range(board, rules, &piece.piece_type));
+
+    DIRECTIONS.into_iter().flat_map(move |(dx, dy)| {
+        let mut blocked = false;
+        (1..=range as isize).map_while(move |step| {
+            let (i, j) = (x as isize + dx * step, y as isize + dy * step);
+            if blocked || i < 0 || j < 0 || !board.layout.contains(i as usize, j as usize) {
+                return None;
+            }
+            let (i, j) = (i as usize, j as usize);
+            if board.layout.is_impassable(i, j) {
+                return None;
+            }
+            let piece_move = Move {
+                from: (x, y),
+                to: (i, j),
+            };
+            match board.get(i, j).unwrap() {
+                None => Some(piece_move),
+                Some(other) => {
+                    blocked = true;
+                    let in_reach = step == 1 || rules.scout_move_and_attack;
+                    (other.owner != piece?.owner && in_reach).then_some(piece_move)
+                }
+            }
+        })
+    })
+}
+
+/// Every legal move of `side`. Searches that look at many positions are better off with
+/// `BitBoard::legal_moves`.
+pub fn legal_moves<'a>(
+    board: &'a Board,
+    rules: &'a RuleSet,
+    side: &'a Side,
+) -> impl Iterator<Item = Move> + 'a {
+    (0..board.squares.len())
+        .filter(move |&i| match &board.squares[i] {
+            Some(piece) => &piece.owner == side,
+            None => false,
+        })
+        .flat_map(move |i| {
+            let (x, y) = board.layout.square(i);
+            legal_moves_from(board, rules, x, y)
+        })
+}
+
+/// Every legal move of `side` that the repetition rules allow after `history`.
+pub fn allowed_moves<'a>(
+    board: &'a Board,
+    rules: &'a RuleSet,
+    history: &'a [PlayedMove],
+    side: &'a Side,
+) -> impl Iterator<Item = Move> + 'a {
+    legal_moves(board, rules, side)
+        .filter(move |piece_move| check_repetition(board, rules, history, side, piece_move).is_ok())
+}
+
+/// How many times in a row a piece may move between the same two squares.
+const TWO_SQUARE_LIMIT: usize = 3;
+
+/// Checks a legal `piece_move` of `side` against the repetition rules, if `rules` enforce them.
+///
+/// The two-square rule stops a piece from moving between the same two squares more than three
+/// times in a row. The more-squares rule covers a piece that keeps moving next to an opposing
+/// piece which keeps moving away: while the chase lasts, the chaser may not bring back a position
+/// that came up during it. Attacks never break either rule.
+pub fn check_repetition(
+    board: &Board,
+    rules: &RuleSet,
+    history: &[PlayedMove],
+    side: &Side,
+    piece_move: &Move,
+) -> Result<(), MoveError> {
+    let attack = matches!(board.get(piece_move.to.0, piece_move.to.1), Some(Some(_)));
+    if !rules.repetition_rules || attack {
+        return Ok(());
+    }
+
+    // the moves the piece made in a row, latest first
+    let mut expected = piece_move.from;
+    let run = history
+        .iter()
+        .rev()
+        .filter(|played| &played.side == side)
+        .take_while(|played| {
+            let continues =
+                played.response == MoveResponse::Success && played.piece_move.to == expected;
+            expected = played.piece_move.from;
+            continues
+        });
+    let squares = |m: &Move| {
+        if m.from < m.to {
+            (m.from, m.to)
+        } else {
+            (m.to, m.from)
+        }
+    };
+    let repeats = run
+        .take_while(|played| squares(&played.piece_move) == squares(piece_move))
+        .count();
+    if repeats >= TWO_SQUARE_LIMIT {
+        return Err(MoveError::Repetition(RepetitionRule::TwoSquare));
+    }
+
+    // the chased piece is whatever the opponent moved last, if the move closes in on it again
+    let Some(last) = history.last() else {
+        return Ok(());
+    };
+    if &last.side == side || !adjacent(piece_move.to, last.piece_move.to) {
+        return Ok(());
+    }
+    let chased_now = last.piece_move.to;
+    let (mut chaser, mut chased) = (piece_move.from, chased_now);
+    // positions are told apart by their hashes, taking the chase back on a copy of the board
+    let mut next = board.clone();
+    shift(&mut next, piece_move.from, piece_move.to);
+    let next = next.position_hash(!side);
+    let mut past = board.clone();
+    for pair in history.rchunks_exact(2) {
+        let (chase, reply) = (&pair[0], &pair[1]);
+        let continues = &chase.side == side
+            && chase.response == MoveResponse::Success
+            && reply.response == MoveResponse::Success
+            && chase.piece_move.to == chaser
+            && reply.piece_move.to == chased
+            && adjacent(chase.piece_move.to, reply.piece_move.from);
+        if !continues {
+            break;
+        }
+        shift(&mut past, reply.piece_move.to, reply.piece_move.from);
+        if past.position_hash(!side) == next {
+            return Err(MoveError::Repetition(RepetitionRule::MoreSquares));
+        }
+        shift(&mut past, chase.piece_move.to, chase.piece_move.from);
+        chaser = chase.piece_move.from;
+        chased = reply.piece_move.from;
+    }
+
+    Ok(())
+}
+
+/// Moves whatever stands on `from` to `to`, leaving what is known about it as it is.
+fn shift(board: &mut Board, from: (usize, usize), to: (usize, usize)) {
+    let piece = board.get(from.0, from.1).cloned().flatten();
+    board.set(from.0, from.1, None);
+    board.set(to.0, to.1, piece);
+}
+
+fn adjacent(a: (usize, usize), b: (usize, usize)) -> bool {
+    a.0.abs_diff(b.0) + a.1.abs_diff(b.1) == 1
+}
+
 pub type MoveResult = Result<MoveResponse, MoveError>;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
 pub enum MoveResponse {
     Success,
     AttackSuccess(Piece),
@@ -143,7 +278,21 @@ pub enum MoveResponse {
     AttackFailureMutual(Piece, Piece),
 }
 
-#[derive(Error, Debug, Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Move {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+}
+
+/// A move that has been made, with everything both sides saw of it.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct PlayedMove {
+    pub side: Side,
+    pub piece_move: Move,
+    pub response: MoveResponse,
+}
+
+#[derive(Error, Clone, Debug, Deserialize, Serialize)]
 pub enum MoveError {
     #[error("Invalid Location")]
     InvalidLocation,
@@ -159,30 +308,692 @@ pub enum MoveError {
     Immovable,
     #[error("Piece Not Found At ({0}, {1})")]
     PieceNotFound(usize, usize),
+    #[error("Game Over")]
+    GameOver,
+    #[error("Game Does Not Exist")]
+    GameDoesNotExist,
+    #[error("Access Denied")]
+    InvalidAccess,
+    #[error("Not Your Turn")]
+    NotYourTurn,
+    #[error("Not Allowed While {0}")]
+    OutOfPhase(GamePhase),
+    #[error("Forbidden by the {0}")]
+    Repetition(RepetitionRule),
+}
+
+/// A rule against playing the same moves over and over.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Display)]
+pub enum RepetitionRule {
+    #[strum(serialize = "Two-Square Rule")]
+    TwoSquare,
+    #[strum(serialize = "More-Squares Rule")]
+    MoreSquares,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub enum GameOutcome {
+    /// The flag of the losing side was captured.
+    FlagCaptured(Side),
+    /// The side to move had nothing left it could move.
+    NoMovablePieces(Side),
+    /// Neither side has anything left it could move.
+    Draw,
+    Resignation(Side),
+    /// The losing side ran out of time.
+    Timeout(Side),
+}
+
+impl GameOutcome {
+    pub fn winner(&self) -> Option<&Side> {
+        match self {
+            GameOutcome::FlagCaptured(winner) => Some(winner),
+            GameOutcome::NoMovablePieces(winner) => Some(winner),
+            GameOutcome::Draw => None,
+            GameOutcome::Resignation(winner) => Some(winner),
+            GameOutcome::Timeout(winner) => Some(winner),
+        }
+    }
+}
+
+pub fn can_move(board: &Board, rules: &RuleSet, side: &Side) -> bool {
+    legal_moves(board, rules, side).next().is_some()
+}
+
+fn has_flag(board: &Board, side: &Side) -> bool {
+    board.squares.iter().any(|piece| {
+        if let Some(piece) = piece {
+            &piece.owner == side && piece.piece_type == PieceType::Flag
+        } else {
+            false
+        }
+    })
 }
 
-pub fn move_piece(board: &mut Board, id: Uuid, x: usize, y: usize) -> MoveResult {
-    let res = valid_move_from_id(board, id, x, y)?;
+/// Checks a board in play for a finished game, `side_to_move` being the side whose turn is next.
+/// A side that only has moves the repetition rules forbid after `history` cannot move.
+pub fn game_outcome(
+    board: &Board,
+    rules: &RuleSet,
+    history: &[PlayedMove],
+    side_to_move: &Side,
+) -> Option<GameOutcome> {
+    if !has_flag(board, side_to_move) {
+        return Some(GameOutcome::FlagCaptured(!side_to_move.clone()));
+    }
+    if !has_flag(board, !side_to_move) {
+        return Some(GameOutcome::FlagCaptured(side_to_move.clone()));
+    }
+
+    if allowed_moves(board, rules, history, side_to_move)
+        .next()
+        .is_none()
+    {
+        if can_move(board, rules, !side_to_move) {
+            return Some(GameOutcome::NoMovablePieces(!side_to_move.clone()));
+        }
+        return Some(GameOutcome::Draw);
+    }
+
+    None
+}
 
+pub fn move_piece(board: &mut Board, rules: &RuleSet, id: Uuid, x: usize, y: usize) -> MoveResult {
     let position = board.find(id).ok_or(MoveError::PieceDoesNotExist(id))?;
-    match res {
-        MoveResponse::Success => {
-            board.set(x, y, board.get(position.0, position.1).unwrap().clone());
-            board.set(position.0, position.1, None);
-        },
-        MoveResponse::AttackSuccess(_) => {
-            board.set(x, y, board.get(position.0, position.1).unwrap().clone());
-            board.set(position.0, position.1, None);
-        },
-        MoveResponse::AttackFailure(_) => {
-            board.set(position.0, position.1, None);
-        },
-        MoveResponse::AttackFailureMutual(_, _) => {
-            board.set(x, y, board.get(position.0, position.1).unwrap().clone());
-            board.set(position.0, position.1, None);
+    let record = MoveRecord::new(
+        board,
+        rules,
+        &Move {
+            from: position,
+            to: (x, y),
         },
+    )?;
+    record.apply(board);
+
+    Ok(record.response)
+}
+
+/// A move together with everything it changes on the board, so it can be taken back.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct MoveRecord {
+    /// The moving piece as it was before the move.
+    pub mover: Piece,
+    pub piece_move: Move,
+    pub response: MoveResponse,
+    /// What stood on the target square before the move.
+    pub defender: Option<Piece>,
+}
+
+impl MoveRecord {
+    /// Checks `piece_move` against `rules` and records what it would do to `board`.
+    pub fn new(board: &Board, rules: &RuleSet, piece_move: &Move) -> Result<Self, MoveError> {
+        let (u, v) = piece_move.from;
+        let (x, y) = piece_move.to;
+        let response = valid_move(board, rules, u, v, x, y)?;
+
+        Ok(Self {
+            mover: board.get(u, v).unwrap().clone().unwrap(),
+            piece_move: *piece_move,
+            response,
+            defender: board.get(x, y).unwrap().clone(),
+        })
+    }
+
+    /// The pieces the move takes off the board.
+    pub fn removed(&self) -> Vec<&Piece> {
+        match &self.response {
+            MoveResponse::Success => vec![],
+            MoveResponse::AttackSuccess(lost) | MoveResponse::AttackFailure(lost) => vec![lost],
+            MoveResponse::AttackFailureMutual(defender, attacker) => vec![defender, attacker],
+        }
+    }
+
+    /// Makes the move on `board`, which has to be the board it was recorded on.
+    pub fn apply(&self, board: &mut Board) {
+        let (from, to) = (self.piece_move.from, self.piece_move.to);
+        let mut piece = self.mover.clone();
+
+        piece.knowledge.moved = true;
+        if from.0.abs_diff(to.0) + from.1.abs_diff(to.1) > 1 {
+            piece.knowledge.scouted = true;
+        }
+
+        board.set(from.0, from.1, None);
+        match &self.response {
+            MoveResponse::Success => {
+                board.set(to.0, to.1, Some(piece));
+            }
+            MoveResponse::AttackSuccess(_) => {
+                piece.knowledge.revealed = true;
+                board.set(to.0, to.1, Some(piece));
+            }
+            MoveResponse::AttackFailure(_) => {
+                let mut defender = self.defender.clone();
+                if let Some(defender) = &mut defender {
+                    defender.knowledge.revealed = true;
+                }
+                board.set(to.0, to.1, defender);
+            }
+            MoveResponse::AttackFailureMutual(_, _) => {
+                board.set(to.0, to.1, None);
+            }
+        }
     }
-    
 
-    Ok(res)
+    /// Takes the move back, leaving `board` as it was before `apply`.
+    pub fn unapply(&self, board: &mut Board) {
+        let (from, to) = (self.piece_move.from, self.piece_move.to);
+        board.set(from.0, from.1, Some(self.mover.clone()));
+        board.set(to.0, to.1, self.defender.clone());
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::rules::Variant;
+    use crate::PieceKnowledge;
+
+    pub(crate) fn piece(owner: Side, piece_type: PieceType) -> Piece {
+        Piece {
+            id: Uuid::new_v4(),
+            owner,
+            piece_type,
+            knowledge: Default::default(),
+        }
+    }
+
+    /// A piece of a side put on a square.
+    pub(crate) type Placed = ((usize, usize), Side, PieceType);
+    type Squares = ((usize, usize), (usize, usize));
+
+    /// A classic board with only `pieces` on it.
+    pub(crate) fn board(pieces: &[Placed]) -> Board {
+        let mut board = Board::default();
+        for ((x, y), owner, piece_type) in pieces {
+            board.set(*x, *y, Some(piece(owner.clone(), piece_type.clone())));
+        }
+        board
+    }
+
+    /// Positions from games of random legal moves after random setups, seeded so they are the
+    /// same on every run.
+    pub(crate) fn sample_positions() -> Vec<(Board, RuleSet)> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut positions = Vec::new();
+
+        for variant in [Variant::Classic, Variant::Duel] {
+            for scout_move_and_attack in [true, false] {
+                let rules = RuleSet {
+                    scout_move_and_attack,
+                    ..variant.rules()
+                };
+                let mut board = Board::new(rules.layout.clone());
+                for (primary, side) in [(true, Side::Red), (false, Side::Blue)] {
+                    let zone = rules.layout.setup_zone(primary);
+                    let mut setup = rules
+                        .pieces
+                        .iter()
+                        .flat_map(|piece| vec![Some(piece.piece_type.clone()); piece.count])
+                        .collect::<Vec<_>>();
+                    setup.resize(zone.len(), None);
+                    setup.shuffle(&mut rng);
+                    for (&(x, y), piece_type) in zone.iter().zip(setup) {
+                        board.set(x, y, piece_type.map(|t| piece(side.clone(), t)));
+                    }
+                }
+
+                let mut side = Side::Red;
+                for turn in 0..200 {
+                    if turn % 20 == 0 {
+                        positions.push((board.clone(), rules.clone()));
+                    }
+                    let moves = legal_moves(&board, &rules, &side).collect::<Vec<_>>();
+                    let Some(piece_move) = moves.choose(&mut rng) else {
+                        break;
+                    };
+                    MoveRecord::new(&board, &rules, piece_move)
+                        .unwrap()
+                        .apply(&mut board);
+                    side = !side;
+                }
+            }
+        }
+
+        positions
+    }
+
+    /// Every square of `board`.
+    pub(crate) fn squares(board: &Board) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..board.squares.len()).map(|index| board.layout.square(index))
+    }
+
+    #[test]
+    fn legal_moves_from_agrees_with_valid_move() {
+        for (board, rules) in sample_positions() {
+            for from in squares(&board) {
+                let legal = legal_moves_from(&board, &rules, from.0, from.1).collect::<Vec<_>>();
+                for to in squares(&board) {
+                    let valid = valid_move(&board, &rules, from.0, from.1, to.0, to.1);
+                    assert_eq!(
+                        legal.contains(&Move { from, to }),
+                        valid.is_ok(),
+                        "{:?} to {:?}: {:?}",
+                        from,
+                        to,
+                        valid
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scout_moves() {
+        use PieceType::*;
+        use Side::*;
+
+        // the lakes are (2, 4), (3, 4), (2, 5), (3, 5), (6, 4), (7, 4), (6, 5) and (7, 5)
+        // what is on the board, the move, whether move-and-attack is on and whether it is legal
+        let cases: &[(&str, &[Placed], Squares, bool, bool)] = &[
+            (
+                "up to a lake",
+                &[((2, 8), Red, Scout)],
+                ((2, 8), (2, 6)),
+                true,
+                true,
+            ),
+            (
+                "into a lake",
+                &[((2, 8), Red, Scout)],
+                ((2, 8), (2, 5)),
+                true,
+                false,
+            ),
+            (
+                "across a lake",
+                &[((2, 8), Red, Scout)],
+                ((2, 8), (2, 2)),
+                true,
+                false,
+            ),
+            (
+                "sideways across a lake",
+                &[((0, 4), Red, Scout)],
+                ((0, 4), (4, 4)),
+                true,
+                false,
+            ),
+            (
+                "between the lakes",
+                &[((4, 9), Red, Scout)],
+                ((4, 9), (4, 0)),
+                true,
+                true,
+            ),
+            (
+                "up to an own piece",
+                &[((5, 8), Red, Scout), ((5, 3), Red, Marshal)],
+                ((5, 8), (5, 4)),
+                true,
+                true,
+            ),
+            (
+                "past an own piece",
+                &[((5, 8), Red, Scout), ((5, 3), Red, Marshal)],
+                ((5, 8), (5, 2)),
+                true,
+                false,
+            ),
+            (
+                "onto an own piece",
+                &[((5, 8), Red, Scout), ((5, 3), Red, Marshal)],
+                ((5, 8), (5, 3)),
+                true,
+                false,
+            ),
+            (
+                "past an opposing piece",
+                &[((5, 8), Red, Scout), ((5, 3), Blue, Marshal)],
+                ((5, 8), (5, 2)),
+                true,
+                false,
+            ),
+            (
+                "past an opposing piece without move-and-attack",
+                &[((5, 8), Red, Scout), ((5, 3), Blue, Marshal)],
+                ((5, 8), (5, 2)),
+                false,
+                false,
+            ),
+            (
+                "along the bottom edge",
+                &[((0, 9), Red, Scout)],
+                ((0, 9), (9, 9)),
+                true,
+                true,
+            ),
+            (
+                "along the right edge",
+                &[((9, 9), Red, Scout)],
+                ((9, 9), (9, 0)),
+                true,
+                true,
+            ),
+            (
+                "off the board",
+                &[((9, 0), Red, Scout)],
+                ((9, 0), (10, 0)),
+                true,
+                false,
+            ),
+            (
+                "diagonally",
+                &[((0, 9), Red, Scout)],
+                ((0, 9), (1, 8)),
+                true,
+                false,
+            ),
+            (
+                "attacking from afar",
+                &[((5, 8), Red, Scout), ((5, 3), Blue, Marshal)],
+                ((5, 8), (5, 3)),
+                true,
+                true,
+            ),
+            (
+                "attacking from afar without move-and-attack",
+                &[((5, 8), Red, Scout), ((5, 3), Blue, Marshal)],
+                ((5, 8), (5, 3)),
+                false,
+                false,
+            ),
+            (
+                "attacking next to it without move-and-attack",
+                &[((5, 4), Red, Scout), ((5, 3), Blue, Marshal)],
+                ((5, 4), (5, 3)),
+                false,
+                true,
+            ),
+            (
+                "attacking across the edge of a lake",
+                &[((1, 4), Red, Scout), ((4, 4), Blue, Marshal)],
+                ((1, 4), (4, 4)),
+                true,
+                false,
+            ),
+        ];
+
+        for &(name, pieces, (from, to), scout_move_and_attack, legal) in cases {
+            let board = board(pieces);
+            let rules = RuleSet {
+                scout_move_and_attack,
+                ..RuleSet::default()
+            };
+
+            let valid = valid_move(&board, &rules, from.0, from.1, to.0, to.1);
+            assert_eq!(valid.is_ok(), legal, "{}: {:?}", name, valid);
+            let generated = legal_moves_from(&board, &rules, from.0, from.1)
+                .any(|piece_move| piece_move == Move { from, to });
+            assert_eq!(generated, legal, "{}", name);
+        }
+    }
+
+    #[test]
+    fn repetition_rules() {
+        use PieceType::*;
+        use Side::*;
+
+        let played = |side, from, to| PlayedMove {
+            side,
+            piece_move: Move { from, to },
+            response: MoveResponse::Success,
+        };
+        let attack = |side, from, to| PlayedMove {
+            response: MoveResponse::AttackSuccess(piece(Red, Scout)),
+            ..played(side, from, to)
+        };
+        // a red Major chasing a blue Captain around (4, 0) and (5, 0)
+        let chase = [
+            played(Red, (4, 2), (4, 1)),
+            played(Blue, (4, 0), (5, 0)),
+            played(Red, (4, 1), (5, 1)),
+            played(Blue, (5, 0), (4, 0)),
+        ];
+        let chase_board = &[((5, 1), Red, Major), ((4, 0), Blue, Captain)];
+
+        // what is on the board, the moves so far, the move and what forbids it
+        type Case<'a> = (
+            &'a str,
+            &'a [Placed],
+            Vec<PlayedMove>,
+            Squares,
+            Option<RepetitionRule>,
+        );
+        let cases: &[Case] = &[
+            (
+                "a third time between two squares",
+                &[((0, 6), Red, Major), ((9, 3), Blue, Major)],
+                vec![
+                    played(Red, (0, 6), (0, 5)),
+                    played(Blue, (9, 3), (9, 4)),
+                    played(Red, (0, 5), (0, 6)),
+                    played(Blue, (9, 4), (9, 3)),
+                ],
+                ((0, 6), (0, 5)),
+                None,
+            ),
+            (
+                "a fourth time between two squares",
+                &[((0, 5), Red, Major), ((9, 4), Blue, Major)],
+                vec![
+                    played(Red, (0, 6), (0, 5)),
+                    played(Blue, (9, 3), (9, 4)),
+                    played(Red, (0, 5), (0, 6)),
+                    played(Blue, (9, 4), (9, 3)),
+                    played(Red, (0, 6), (0, 5)),
+                    played(Blue, (9, 3), (9, 4)),
+                ],
+                ((0, 5), (0, 6)),
+                Some(RepetitionRule::TwoSquare),
+            ),
+            (
+                "to a third square after three times",
+                &[((0, 5), Red, Major), ((9, 4), Blue, Major)],
+                vec![
+                    played(Red, (0, 6), (0, 5)),
+                    played(Blue, (9, 3), (9, 4)),
+                    played(Red, (0, 5), (0, 6)),
+                    played(Blue, (9, 4), (9, 3)),
+                    played(Red, (0, 6), (0, 5)),
+                    played(Blue, (9, 3), (9, 4)),
+                ],
+                ((0, 5), (1, 5)),
+                None,
+            ),
+            (
+                "back into a position of the chase",
+                chase_board,
+                chase.to_vec(),
+                ((5, 1), (4, 1)),
+                Some(RepetitionRule::MoreSquares),
+            ),
+            (
+                "back into a position of a chase broken by an attack",
+                chase_board,
+                vec![
+                    chase[0].clone(),
+                    attack(Blue, (4, 0), (5, 0)),
+                    chase[2].clone(),
+                    chase[3].clone(),
+                ],
+                ((5, 1), (4, 1)),
+                None,
+            ),
+            (
+                "back to a square without chasing",
+                &[((4, 1), Red, Major), ((9, 0), Blue, Major)],
+                vec![
+                    played(Red, (4, 2), (4, 1)),
+                    played(Blue, (9, 0), (9, 1)),
+                    played(Red, (4, 1), (4, 2)),
+                    played(Blue, (9, 1), (9, 0)),
+                ],
+                ((4, 2), (4, 1)),
+                None,
+            ),
+            (
+                "off the board",
+                &[((9, 0), Red, Major)],
+                vec![],
+                ((9, 0), (10, 0)),
+                None,
+            ),
+        ];
+
+        let rules = RuleSet::default();
+        for (name, pieces, history, (from, to), forbidden) in cases {
+            let board = board(pieces);
+            let piece_move = Move {
+                from: *from,
+                to: *to,
+            };
+            let checked = check_repetition(&board, &rules, history, &Red, &piece_move);
+            let rule = match checked {
+                Err(MoveError::Repetition(rule)) => Some(rule),
+                _ => None,
+            };
+            assert_eq!(&rule, forbidden, "{}", name);
+        }
+    }
+
+    #[test]
+    fn move_record_round_trip() {
+        use PieceType::*;
+        use Side::*;
+
+        let knowledge = |revealed, moved, scouted| PieceKnowledge {
+            revealed,
+            moved,
+            scouted,
+        };
+        // what is on the board, the move, how many pieces it takes off and what is left on the
+        // target square
+        type Case<'a> = (
+            &'a str,
+            &'a [Placed],
+            Squares,
+            usize,
+            Option<(Side, PieceType, PieceKnowledge)>,
+        );
+        let cases: &[Case] = &[
+            (
+                "a step",
+                &[((0, 6), Red, Major)],
+                ((0, 6), (0, 5)),
+                0,
+                Some((Red, Major, knowledge(false, true, false))),
+            ),
+            (
+                "a Scout running",
+                &[((0, 6), Red, Scout)],
+                ((0, 6), (0, 3)),
+                0,
+                Some((Red, Scout, knowledge(false, true, true))),
+            ),
+            (
+                "taking a piece",
+                &[((0, 6), Red, Major), ((0, 5), Blue, Captain)],
+                ((0, 6), (0, 5)),
+                1,
+                Some((Red, Major, knowledge(true, true, false))),
+            ),
+            (
+                "running into a Bomb",
+                &[((0, 6), Red, Major), ((0, 5), Blue, Bomb)],
+                ((0, 6), (0, 5)),
+                1,
+                Some((Blue, Bomb, knowledge(true, false, false))),
+            ),
+            (
+                "trading",
+                &[((0, 6), Red, Major), ((0, 5), Blue, Major)],
+                ((0, 6), (0, 5)),
+                2,
+                None,
+            ),
+            (
+                "a Scout running into a trade",
+                &[((0, 9), Red, Scout), ((0, 3), Blue, Scout)],
+                ((0, 9), (0, 3)),
+                2,
+                None,
+            ),
+        ];
+
+        let rules = RuleSet::default();
+        for (name, pieces, (from, to), removed, left) in cases {
+            let mut board = board(pieces);
+            let before = board.clone();
+            let piece_move = Move {
+                from: *from,
+                to: *to,
+            };
+            let record = MoveRecord::new(&board, &rules, &piece_move).unwrap();
+            assert_eq!(record.removed().len(), *removed, "{}", name);
+
+            record.apply(&mut board);
+            assert_eq!(board.get(from.0, from.1), Some(&None), "{}", name);
+            let target = board.get(to.0, to.1).unwrap().as_ref().map(|piece| {
+                let Piece {
+                    owner,
+                    piece_type,
+                    knowledge,
+                    ..
+                } = piece.clone();
+                (owner, piece_type, knowledge)
+            });
+            assert_eq!(&target, left, "{}", name);
+
+            record.unapply(&mut board);
+            assert_eq!(board, before, "{}", name);
+        }
+
+        for (mut board, rules) in sample_positions() {
+            let before = board.clone();
+            for side in [Red, Blue] {
+                for piece_move in legal_moves(&before, &rules, &side) {
+                    let record = MoveRecord::new(&board, &rules, &piece_move).unwrap();
+                    record.apply(&mut board);
+                    record.unapply(&mut board);
+                    assert_eq!(board, before, "{:?}", piece_move);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_hash() {
+        let rehashed = |board: &Board| {
+            let mut board = board.clone();
+            board.rehash();
+            board.hash()
+        };
+        for (mut board, rules) in sample_positions() {
+            let before = board.clone();
+            for side in [Side::Red, Side::Blue] {
+                for piece_move in legal_moves(&before, &rules, &side) {
+                    let record = MoveRecord::new(&board, &rules, &piece_move).unwrap();
+                    record.apply(&mut board);
+                    assert_eq!(board.hash(), rehashed(&board), "{:?}", piece_move);
+                    record.unapply(&mut board);
+                    assert_eq!(board.hash(), rehashed(&board), "{:?}", piece_move);
+                }
+            }
+        }
+    }
 }
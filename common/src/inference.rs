@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::catalog::Movement;
+use crate::game_logic::{Move, MoveResponse, PlayedMove};
+use crate::rules::{Combat, RuleSet};
+use crate::{Board, PieceType, Side};
+
+pub type Distribution = HashMap<PieceType, f32>;
+
+const FITTING_ROUNDS: usize = 50;
+
+/// The types the opponent of `side` can still have among its hidden pieces: the starting counts
+/// of `rules` less whatever is shown on `view` and whatever `history` shows was taken off the
+/// board.
+pub fn remaining_counts(
+    view: &Board,
+    rules: &RuleSet,
+    side: &Side,
+    history: &[PlayedMove],
+) -> HashMap<PieceType, usize> {
+    let shown = view.count_for(!side);
+    let mut remaining = rules
+        .pieces
+        .iter()
+        .map(|piece| {
+            let count = piece
+                .count
+                .saturating_sub(*shown.get(&piece.piece_type).unwrap_or(&0));
+            (piece.piece_type.clone(), count)
+        })
+        .collect::<HashMap<_, _>>();
+
+    for played in history {
+        let lost = match &played.response {
+            MoveResponse::Success => vec![],
+            MoveResponse::AttackSuccess(lost) | MoveResponse::AttackFailure(lost) => vec![lost],
+            MoveResponse::AttackFailureMutual(defender, attacker) => vec![defender, attacker],
+        };
+        for piece in lost {
+            if &piece.owner != side {
+                if let Some(count) = remaining.get_mut(&piece.piece_type) {
+                    *count = count.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    remaining
+}
+
+/// Something a piece showed about its type.
+#[derive(Clone, PartialEq, Debug)]
+enum Evidence {
+    Moved,
+    /// It moved more than one square at once.
+    Scouted,
+    /// It won an attack on this type.
+    Beat(PieceType),
+    /// It held off an attack by this type.
+    Held(PieceType),
+}
+
+impl Evidence {
+    /// Whether a piece of `piece_type` could have shown this.
+    fn fits(&self, rules: &RuleSet, piece_type: &PieceType) -> bool {
+        match self {
+            Evidence::Moved => rules.movement(piece_type) != Movement::Immovable,
+            Evidence::Scouted => rules.movement(piece_type) == Movement::Line,
+            Evidence::Beat(defender) => rules.resolve(piece_type, defender) == Combat::AttackerWins,
+            Evidence::Held(attacker) => rules.resolve(attacker, piece_type) == Combat::DefenderWins,
+        }
+    }
+}
+
+/// What `history` shows of the opposing pieces of `side` still on the board, by the square they
+/// are on now.
+fn evidence(side: &Side, history: &[PlayedMove]) -> HashMap<(usize, usize), Vec<Evidence>> {
+    let mut evidence = HashMap::<_, Vec<_>>::new();
+
+    for played in history {
+        let Move { from, to } = played.piece_move;
+        if &played.side == side {
+            match &played.response {
+                MoveResponse::AttackFailure(attacker) => evidence
+                    .entry(to)
+                    .or_default()
+                    .push(Evidence::Held(attacker.piece_type.clone())),
+                _ => {
+                    evidence.remove(&to);
+                }
+            }
+            continue;
+        }
+
+        let mut shown = evidence.remove(&from).unwrap_or_default();
+        shown.push(Evidence::Moved);
+        if from.0.abs_diff(to.0) + from.1.abs_diff(to.1) > 1 {
+            shown.push(Evidence::Scouted);
+        }
+        match &played.response {
+            MoveResponse::Success => {}
+            MoveResponse::AttackSuccess(defender) => {
+                shown.push(Evidence::Beat(defender.piece_type.clone()))
+            }
+            // the piece is off the board
+            MoveResponse::AttackFailure(_) | MoveResponse::AttackFailureMutual(_, _) => continue,
+        }
+        evidence.insert(to, shown);
+    }
+
+    evidence
+}
+
+/// The chance of each type for every hidden opposing piece on `view`, keyed by piece id.
+///
+/// Every way of handing the remaining types out to the hidden pieces is taken as equally likely
+/// before looking at how the pieces behaved. Then each piece keeps only the types that fit what it
+/// showed: moving at all, moving several squares at once, and winning or holding off an attack.
+/// The result is fitted so each piece's chances add up to one and the expected number of each
+/// type matches what is left of it.
+pub fn infer(
+    view: &Board,
+    rules: &RuleSet,
+    side: &Side,
+    history: &[PlayedMove],
+) -> HashMap<Uuid, Distribution> {
+    let remaining = remaining_counts(view, rules, side, history);
+    let types = remaining
+        .iter()
+        .filter(|(_, count)| **count > 0)
+        .map(|(piece_type, _)| piece_type.clone())
+        .collect::<Vec<_>>();
+
+    let mut seen = evidence(side, history);
+    let hidden = view
+        .squares
+        .iter()
+        .enumerate()
+        .filter_map(|(index, piece)| {
+            let piece = piece.as_ref()?;
+            if &piece.owner == side || piece.piece_type != PieceType::Unknown {
+                return None;
+            }
+            // the knowledge covers what a shortened history may have left out
+            let mut shown = seen.remove(&view.layout.square(index)).unwrap_or_default();
+            if piece.knowledge.moved {
+                shown.push(Evidence::Moved);
+            }
+            if piece.knowledge.scouted {
+                shown.push(Evidence::Scouted);
+            }
+            Some((piece, shown))
+        })
+        .collect::<Vec<_>>();
+
+    // likelihood of what was seen of each piece given each type
+    let mut weights = hidden
+        .iter()
+        .map(|(_, shown)| {
+            types
+                .iter()
+                .map(|piece_type| {
+                    let fits = shown
+                        .iter()
+                        .all(|evidence| evidence.fits(rules, piece_type));
+                    if fits {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                })
+                .collect::<Vec<f32>>()
+        })
+        .collect::<Vec<_>>();
+
+    // the history may not account for every capture, so scale the counts to the hidden pieces
+    let total = types.iter().map(|t| remaining[t]).sum::<usize>() as f32;
+    let targets = types
+        .iter()
+        .map(|t| remaining[t] as f32 * hidden.len() as f32 / total.max(1.0))
+        .collect::<Vec<_>>();
+
+    for _ in 0..FITTING_ROUNDS {
+        for (j, target) in targets.iter().enumerate() {
+            let column = weights.iter().map(|row| row[j]).sum::<f32>();
+            if column > 0.0 {
+                for row in weights.iter_mut() {
+                    row[j] *= target / column;
+                }
+            }
+        }
+        for row in weights.iter_mut() {
+            let sum = row.iter().sum::<f32>();
+            if sum > 0.0 {
+                row.iter_mut().for_each(|weight| *weight /= sum);
+            }
+        }
+    }
+
+    hidden
+        .iter()
+        .zip(weights)
+        .map(|((piece, _), row)| {
+            let distribution = types.iter().cloned().zip(row).collect::<Distribution>();
+            (piece.id, distribution)
+        })
+        .collect()
+}
+
+/// The likeliest type in `distribution` with its chance, if it has any types at all.
+pub fn most_likely(distribution: &Distribution) -> Option<(&PieceType, &f32)> {
+    distribution.iter().max_by(|a, b| a.1.total_cmp(b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::tests::piece;
+
+    /// The types `infer` leaves a chance for, red looking at a blue piece on (4, 4) after `played`.
+    fn possible(played: PlayedMove) -> Vec<PieceType> {
+        let rules = RuleSet::default();
+        let mut view = Board::default();
+        let hidden = piece(Side::Blue, PieceType::Unknown);
+        let id = hidden.id;
+        view.set(4, 4, Some(hidden));
+
+        let beliefs = infer(&view, &rules, &Side::Red, &[played]);
+        let mut possible = beliefs[&id]
+            .iter()
+            .filter(|(_, chance)| **chance > 0.0)
+            .map(|(piece_type, _)| piece_type.clone())
+            .collect::<Vec<_>>();
+        possible.sort();
+        possible
+    }
+
+    #[test]
+    fn evidence_rules_out_types() {
+        let played = |side, from, response| PlayedMove {
+            side,
+            piece_move: Move { from, to: (4, 4) },
+            response,
+        };
+
+        let cases = [
+            (
+                "took a Colonel",
+                played(
+                    Side::Blue,
+                    (4, 3),
+                    MoveResponse::AttackSuccess(piece(Side::Red, PieceType::Colonel)),
+                ),
+                vec![PieceType::Marshal, PieceType::General],
+            ),
+            (
+                "held off a Marshal",
+                played(
+                    Side::Red,
+                    (4, 5),
+                    MoveResponse::AttackFailure(piece(Side::Red, PieceType::Marshal)),
+                ),
+                vec![PieceType::Bomb],
+            ),
+            (
+                "moved three squares",
+                played(Side::Blue, (4, 1), MoveResponse::Success),
+                vec![PieceType::Scout],
+            ),
+            (
+                "moved one square",
+                played(Side::Blue, (4, 3), MoveResponse::Success),
+                vec![
+                    PieceType::Marshal,
+                    PieceType::General,
+                    PieceType::Colonel,
+                    PieceType::Major,
+                    PieceType::Captain,
+                    PieceType::Lieutenant,
+                    PieceType::Sergeant,
+                    PieceType::Miner,
+                    PieceType::Scout,
+                    PieceType::Spy,
+                ],
+            ),
+        ];
+        for (name, played, expected) in cases {
+            assert_eq!(possible(played), expected, "{}", name);
+        }
+    }
+}
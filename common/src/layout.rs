@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+/// The most squares a board may have along either side.
+pub const MAX_LENGTH: usize = 128;
+
+/// The shape of a board: its size, the squares no piece may enter and where each side sets up.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct BoardLayout {
+    pub width: usize,
+    pub height: usize,
+    /// Squares no piece may enter or move across, like the lakes.
+    pub impassable: Vec<(usize, usize)>,
+    /// The squares each side sets up on, in the order of a setup. The primary side's zone comes
+    /// first.
+    pub setup_zones: [Vec<(usize, usize)>; 2],
+}
+
+impl Default for BoardLayout {
+    fn default() -> Self {
+        Self::classic()
+    }
+}
+
+impl BoardLayout {
+    /// A board where each side sets up in the `setup_rows` rows at its end, front row first. The
+    /// primary side sets up at the bottom and the other side at the top, turned around.
+    pub fn rows(
+        width: usize,
+        height: usize,
+        setup_rows: usize,
+        impassable: Vec<(usize, usize)>,
+    ) -> Self {
+        let primary = (height.saturating_sub(setup_rows)..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .collect::<Vec<_>>();
+        let secondary = primary
+            .iter()
+            .map(|&(x, y)| (width - 1 - x, height - 1 - y))
+            .collect();
+
+        Self {
+            width,
+            height,
+            impassable,
+            setup_zones: [primary, secondary],
+        }
+    }
+
+    pub fn classic() -> Self {
+        Self::rows(
+            10,
+            10,
+            4,
+            vec![
+                (2, 4),
+                (3, 4),
+                (2, 5),
+                (3, 5),
+                (6, 4),
+                (7, 4),
+                (6, 5),
+                (7, 5),
+            ],
+        )
+    }
+
+    /// How many squares the board has. Panics if that overflows, which no valid layout does.
+    pub fn size(&self) -> usize {
+        self.width
+            .checked_mul(self.height)
+            .expect("layout too large, see `is_valid`")
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+
+    pub fn is_impassable(&self, x: usize, y: usize) -> bool {
+        self.impassable.contains(&(x, y))
+    }
+
+    /// Where the square (`x`, `y`) is kept in `Board::squares`.
+    pub fn index(&self, x: usize, y: usize) -> Option<usize> {
+        self.contains(x, y).then_some(x + y * self.width)
+    }
+
+    /// The square kept at `index` in `Board::squares`.
+    pub fn square(&self, index: usize) -> (usize, usize) {
+        (index % self.width, index / self.width)
+    }
+
+    pub fn setup_zone(&self, primary: bool) -> &[(usize, usize)] {
+        &self.setup_zones[if primary { 0 } else { 1 }]
+    }
+
+    /// Whether the board is no longer than `MAX_LENGTH` along either side and every setup square
+    /// is a distinct, passable square on it.
+    pub fn is_valid(&self) -> bool {
+        let mut seen = Vec::new();
+        (1..=MAX_LENGTH).contains(&self.width)
+            && (1..=MAX_LENGTH).contains(&self.height)
+            && self.impassable.iter().all(|&(x, y)| self.contains(x, y))
+            && self.setup_zones.iter().flatten().all(|&square| {
+                let fresh = !seen.contains(&square);
+                seen.push(square);
+                fresh && self.contains(square.0, square.1) && !self.impassable.contains(&square)
+            })
+    }
+}
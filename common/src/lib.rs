@@ -1,65 +1,159 @@
 #![feature(inline_const)]
 #![feature(const_trait_impl)]
 
+pub mod bitboard;
+pub mod bot;
+pub mod catalog;
+pub mod event;
 pub mod game_logic;
+pub mod inference;
+pub mod layout;
+pub mod record;
 #[cfg(feature = "client")]
 pub mod request;
+pub mod rules;
+pub mod setup;
 pub mod utils;
+pub mod zobrist;
 
 use serde::{Deserialize, Serialize};
+use bot::BotDifficulty;
+use game_logic::{GameOutcome, PlayedMove};
+use layout::BoardLayout;
+use rules::RuleSet;
+use setup::Setup;
 use std::collections::HashMap;
-use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
+use std::sync::Arc;
+use strum::{Display, EnumIter, EnumString};
 use thiserror::Error;
-use utils::SendibleArray;
 use uuid::Uuid;
 
-pub const BOARD_SIZE: usize = 10 * 10;
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(from = "BoardSquares")]
+pub struct Board {
+    /// Shared between copies, bots copy boards a lot.
+    pub layout: Arc<BoardLayout>,
+    /// Row by row from the top left, see `BoardLayout::index`. Changed through `set`, or
+    /// followed by `rehash`, to keep the hash current.
+    pub squares: Vec<Option<Piece>>,
+    #[serde(skip)]
+    hash: u64,
+}
+
+/// A board as it is sent, the hash being worked out again on arrival.
+#[derive(Deserialize)]
+struct BoardSquares {
+    layout: Arc<BoardLayout>,
+    squares: Vec<Option<Piece>>,
+}
+
+impl From<BoardSquares> for Board {
+    fn from(board: BoardSquares) -> Self {
+        let mut board = Board {
+            layout: board.layout,
+            squares: board.squares,
+            hash: 0,
+        };
+        board.rehash();
+        board
+    }
+}
 
-#[derive(Clone, PartialEq, Serialize, Deserialize, Default, Debug)]
-pub struct Board(pub SendibleArray<Option<Piece>, BOARD_SIZE>);
+impl Default for Board {
+    fn default() -> Self {
+        Self::new(BoardLayout::default())
+    }
+}
 
 impl Board {
-    pub fn new() -> Self {
-        Self(SendibleArray::default())
+    pub fn new(layout: BoardLayout) -> Self {
+        Self {
+            squares: vec![None; layout.size()],
+            layout: Arc::new(layout),
+            hash: 0,
+        }
     }
     pub fn get(&self, x: usize, y: usize) -> Option<&Option<Piece>> {
-        self.0 .0.get(x + y * 10)
+        self.squares.get(self.layout.index(x, y)?)
     }
     pub fn set(&mut self, x: usize, y: usize, piece: Option<Piece>) {
-        self.0[x + y * 10] = piece;
+        let index = self.layout.index(x, y).expect("square outside of the board");
+        if let Some(old) = &self.squares[index] {
+            self.hash ^= zobrist::piece_key(index, old);
+        }
+        if let Some(new) = &piece {
+            self.hash ^= zobrist::piece_key(index, new);
+        }
+        self.squares[index] = piece;
+    }
+
+    /// The Zobrist hash of the pieces on the board, kept up to date by `set`.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// The hash of the position with `side_to_move` to move.
+    pub fn position_hash(&self, side_to_move: &Side) -> u64 {
+        match side_to_move {
+            Side::Red => self.hash,
+            Side::Blue => self.hash ^ zobrist::BLUE_TO_MOVE,
+        }
+    }
+
+    /// Works the hash out from scratch after `squares` was changed directly.
+    pub fn rehash(&mut self) {
+        self.hash = self
+            .squares
+            .iter()
+            .enumerate()
+            .filter_map(|(index, piece)| Some(zobrist::piece_key(index, piece.as_ref()?)))
+            .fold(0, |hash, key| hash ^ key);
     }
 
     pub fn find(&self, id: Uuid) -> Option<(usize, usize)> {
-        let piece = self.0 .0.iter().enumerate().find(|piece| {
+        let piece = self.squares.iter().enumerate().find(|piece| {
             if let Some(piece) = piece.1 {
                 piece.id == id
             } else {
                 false
             }
         })?;
-        let x = piece.0 % 10;
-        let y = piece.0 / 10;
-        Some((x, y))
+        Some(self.layout.square(piece.0))
+    }
+
+    /// The board as seen by `side`, with every piece it does not own and has not learned the type
+    /// of hidden as `PieceType::Unknown`.
+    /// `None` hides the pieces of both sides.
+    pub fn view_for(&self, side: Option<&Side>) -> Board {
+        let mut board = self.clone();
+        for piece in board.squares.iter_mut().flatten() {
+            if Some(&piece.owner) != side && !piece.knowledge.type_known() {
+                piece.piece_type = PieceType::Unknown;
+            }
+        }
+        board.rehash();
+        board
     }
 
+    /// How many of each type are on the board, leaving out the types that are not.
     pub fn count(&self) -> HashMap<PieceType, usize> {
         let mut counts = HashMap::new();
 
-        for piece_type in PieceType::iter() {
-            counts.insert(
-                piece_type.clone(),
-                self.0
-                     .0
-                    .iter()
-                    .filter(move |&piece| {
-                        if let Some(piece) = piece {
-                            piece.piece_type == piece_type
-                        } else {
-                            false
-                        }
-                    })
-                    .count(),
-            );
+        for piece in self.squares.iter().flatten() {
+            *counts.entry(piece.piece_type.clone()).or_insert(0) += 1;
+        }
+
+        counts
+    }
+
+    /// Like `count`, for the pieces of `side` only.
+    pub fn count_for(&self, side: &Side) -> HashMap<PieceType, usize> {
+        let mut counts = HashMap::new();
+
+        for piece in self.squares.iter().flatten() {
+            if &piece.owner == side {
+                *counts.entry(piece.piece_type.clone()).or_insert(0) += 1;
+            }
         }
 
         counts
@@ -93,6 +187,24 @@ impl std::ops::Not for &Side {
         }
     }
 }
+#[derive(PartialEq, Clone, Debug, EnumString, Display, Deserialize, Serialize)]
+#[strum(serialize_all = "snake_case")]
+pub enum SidePreference {
+    Red,
+    Blue,
+    Either,
+}
+
+impl SidePreference {
+    pub fn accepts(&self, side: &Side) -> bool {
+        match self {
+            SidePreference::Red => side == &Side::Red,
+            SidePreference::Blue => side == &Side::Blue,
+            SidePreference::Either => true,
+        }
+    }
+}
+
 // impl std::ops::Not for &mut Side {
 //     type Output = Self;
 
@@ -109,8 +221,30 @@ pub struct Piece {
     pub id: Uuid,
     pub owner: Side,
     pub piece_type: PieceType,
+    #[serde(default)]
+    pub knowledge: PieceKnowledge,
+}
+
+/// What the opponent of a piece's owner has learned about it.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Default)]
+pub struct PieceKnowledge {
+    /// The piece has been in combat, so its type was shown.
+    pub revealed: bool,
+    /// The piece has moved, so it is neither a Bomb nor a Flag.
+    pub moved: bool,
+    /// The piece has moved more than one square, so it moves in lines. That narrows the type down
+    /// without naming it when the catalog has more than one line mover.
+    pub scouted: bool,
+}
+
+impl PieceKnowledge {
+    pub fn type_known(&self) -> bool {
+        self.revealed
+    }
 }
 
+/// What a piece is. How each type moves and fights is up to the catalog of the rules, see
+/// `catalog::PieceDefinition`.
 #[derive(
     Deserialize,
     Serialize,
@@ -126,40 +260,25 @@ pub struct Piece {
     Default,
 )]
 pub enum PieceType {
-    Bomb = 11,
-    Marshal = 10,
-    General = 9,
-    Colonel = 8,
-    Major = 7,
-    Captain = 6,
-    Lieutenant = 5,
-    Sergeant = 4,
-    Miner = 3,
-    Scout = 2,
-    Spy = 1,
-    Flag = 0,
+    Bomb,
+    Marshal,
+    General,
+    Colonel,
+    Major,
+    Captain,
+    Lieutenant,
+    Sergeant,
+    Miner,
+    Scout,
+    Spy,
+    Flag,
     #[default]
-    Unknown = -1,
+    Unknown,
+    /// A piece only a variant's catalog knows about.
+    Custom(u8),
 }
 
 impl PieceType {
-    pub fn triumphs(&self, other: &Self) -> bool {
-        match self {
-            PieceType::Miner => {
-                if other == &PieceType::Bomb {
-                    return true;
-                }
-            }
-            PieceType::Spy => {
-                if other == &PieceType::Marshal {
-                    return true;
-                }
-            }
-            _ => {}
-        }
-
-        self > other
-    }
     pub fn starting_count(&self) -> usize {
         match self {
             PieceType::Bomb => 6,
@@ -174,7 +293,7 @@ impl PieceType {
             PieceType::Scout => 8,
             PieceType::Spy => 1,
             PieceType::Flag => 1,
-            PieceType::Unknown => 0,
+            PieceType::Unknown | PieceType::Custom(_) => 0,
         }
     }
 }
@@ -183,6 +302,25 @@ impl PieceType {
 pub struct GameInfo {
     pub vs_bot: bool,
     pub primary_side: Side,
+    #[serde(default)]
+    pub spectator_view: SpectatorView,
+    #[serde(default)]
+    pub bot_difficulty: BotDifficulty,
+    #[serde(default)]
+    pub rules: RuleSet,
+    /// Whether players may have the assistant show its guesses at hidden pieces, see
+    /// `inference::infer`.
+    #[serde(default)]
+    pub training: bool,
+}
+
+/// What clients without a side get to see of the board.
+#[derive(Clone, PartialEq, Deserialize, Serialize, Debug, Default)]
+pub enum SpectatorView {
+    #[default]
+    Hidden,
+    Side(Side),
+    Full,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -202,7 +340,23 @@ pub struct PieceMove {
 #[derive(Serialize, Deserialize)]
 pub struct InitState {
     pub access_token: Uuid,
-    pub pieces: SendibleArray<PieceType, 40>,
+    /// What goes on each setup square, in the order of the setup zone of the player's side, see
+    /// `BoardLayout::setup_zones`.
+    #[serde(default)]
+    pub pieces: Vec<Option<PieceType>>,
+    /// The same in the compact notation, used instead of `pieces` when given.
+    #[serde(default)]
+    pub setup: Option<Setup>,
+}
+
+impl InitState {
+    /// What goes on each setup square, from whichever of `setup` and `pieces` was sent.
+    pub fn setup_pieces(&self) -> Vec<Option<PieceType>> {
+        match &self.setup {
+            Some(setup) => setup.pieces(),
+            None => self.pieces.clone(),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Error, Debug)]
@@ -215,11 +369,34 @@ pub enum InitSetupError {
     UnknownFail,
     #[error("Game Does Not Exist")]
     GameDoesNotExist,
+    #[error("Setup Already Done")]
+    AlreadySetup,
+    #[error("Not Allowed While {0}")]
+    OutOfPhase(GamePhase),
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, Display)]
+pub enum GamePhase {
+    WaitingForPlayers,
+    Setup,
+    Playing,
+    Finished,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct BoardState {
     pub board: Board,
+    /// Goes up with every change to the game, see `request::get_game_state_changed`.
+    #[serde(default)]
+    pub version: u64,
     pub active_side: Side,
-    pub ready: bool,
+    pub phase: GamePhase,
+    pub outcome: Option<GameOutcome>,
+    #[serde(default)]
+    pub history: Vec<PlayedMove>,
+    #[serde(default)]
+    pub rules: RuleSet,
+    /// See `GameInfo::training`.
+    #[serde(default)]
+    pub training: bool,
 }
@@ -0,0 +1,408 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::game_logic::{self, GameOutcome, Move, MoveError, PlayedMove};
+use crate::layout::BoardLayout;
+use crate::rules::RuleSet;
+use crate::setup::Setup;
+use crate::{Board, Piece, PieceType, Side};
+
+/// Everything needed to play a game over: its rules, both setups and every move in order.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct GameRecord {
+    #[serde(default)]
+    pub rules: RuleSet,
+    /// The side that set up in the primary zone and moved first.
+    pub primary_side: Side,
+    /// In the order of the red side's setup zone.
+    pub red_setup: Setup,
+    /// In the order of the blue side's setup zone.
+    pub blue_setup: Setup,
+    pub moves: Vec<Move>,
+    #[serde(default)]
+    pub outcome: Option<GameOutcome>,
+}
+
+#[derive(Error, Debug)]
+pub enum RecordError {
+    #[error("Malformed Record: {0}")]
+    Malformed(String),
+    #[error("Only Classic Games Fit Gravon Records")]
+    NotClassic,
+    #[error("Move {0} Is Invalid: {1}")]
+    InvalidMove(usize, MoveError),
+}
+
+impl GameRecord {
+    pub fn setup(&self, side: &Side) -> &Setup {
+        match side {
+            Side::Red => &self.red_setup,
+            Side::Blue => &self.blue_setup,
+        }
+    }
+
+    /// The board after both setups and before the first move.
+    pub fn initial_board(&self) -> Board {
+        let mut board = Board::new(self.rules.layout.clone());
+        for side in [Side::Red, Side::Blue] {
+            let zone = self.rules.layout.setup_zone(side == self.primary_side);
+            for (&(x, y), piece_type) in zone.iter().zip(self.setup(&side).pieces()) {
+                let piece = piece_type.map(|piece_type| Piece {
+                    id: Uuid::new_v4(),
+                    owner: side.clone(),
+                    piece_type,
+                    knowledge: Default::default(),
+                });
+                board.set(x, y, piece);
+            }
+        }
+        board
+    }
+
+    /// Plays every move from the initial board, the sides taking turns. Gives the final board
+    /// and the moves as they were played, or the first move the rules do not allow.
+    pub fn replay(&self) -> Result<(Board, Vec<PlayedMove>), RecordError> {
+        let mut board = self.initial_board();
+        let mut history = Vec::new();
+        let mut side = self.primary_side.clone();
+
+        for (i, piece_move) in self.moves.iter().enumerate() {
+            let invalid = |err| RecordError::InvalidMove(i + 1, err);
+            let (x, y) = piece_move.from;
+            let piece = match board.get(x, y) {
+                Some(Some(piece)) if piece.owner == side => piece.clone(),
+                _ => return Err(invalid(MoveError::PieceNotFound(x, y))),
+            };
+            let response = game_logic::move_piece(
+                &mut board,
+                &self.rules,
+                piece.id,
+                piece_move.to.0,
+                piece_move.to.1,
+            )
+            .map_err(invalid)?;
+
+            history.push(PlayedMove {
+                side: side.clone(),
+                piece_move: *piece_move,
+                response,
+            });
+            side = !side;
+        }
+
+        Ok((board, history))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("records always serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, RecordError> {
+        serde_json::from_str(json).map_err(|err| RecordError::Malformed(err.to_string()))
+    }
+
+    /// Writes the record in the XML format of the Gravon archives. Gravon rows 1 to 4 hold the
+    /// side that moves first, so the board is turned around.
+    pub fn to_gravon(&self) -> Result<String, RecordError> {
+        if self.rules.layout != BoardLayout::classic() {
+            return Err(RecordError::NotClassic);
+        }
+        let board = self.initial_board();
+
+        let mut content = String::new();
+        for index in 0..GRAVON_SIZE * GRAVON_SIZE {
+            let (x, y) = from_gravon_index(index);
+            content.push(match board.get(x, y).unwrap() {
+                Some(piece) => gravon_symbol(&piece.owner, &piece.piece_type)?,
+                None if board.layout.is_impassable(x, y) => '_',
+                None => 'A',
+            });
+        }
+
+        let mut xml =
+            String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<stratego>\n<game>\n");
+        xml.push_str(&format!("<field content=\"{}\"/>\n", content));
+        for (i, piece_move) in self.moves.iter().enumerate() {
+            xml.push_str(&format!(
+                "<move id=\"{}\" source=\"{}\" target=\"{}\"/>\n",
+                i + 1,
+                gravon_square(piece_move.from),
+                gravon_square(piece_move.to),
+            ));
+        }
+        if let Some(outcome) = &self.outcome {
+            let kind = match outcome {
+                GameOutcome::FlagCaptured(_) => 1,
+                GameOutcome::NoMovablePieces(_) | GameOutcome::Draw => 2,
+                GameOutcome::Resignation(_) => 3,
+                GameOutcome::Timeout(_) => 4,
+            };
+            let winner = match outcome.winner() {
+                Some(Side::Red) => 1,
+                Some(Side::Blue) => 2,
+                None => 0,
+            };
+            xml.push_str(&format!(
+                "<result type=\"{}\" winner=\"{}\"/>\n",
+                kind, winner
+            ));
+        }
+        xml.push_str("</game>\n</stratego>\n");
+
+        Ok(xml)
+    }
+
+    /// Reads a game from the XML format of the Gravon archives, played by the classic rules.
+    pub fn from_gravon(xml: &str) -> Result<Self, RecordError> {
+        let malformed = |what: &str| RecordError::Malformed(what.to_owned());
+        let rules = RuleSet::default();
+
+        let mut content = None;
+        let mut moves = Vec::new();
+        let mut outcome = None;
+        for (name, attributes) in xml_tags(xml) {
+            let attribute = |key: &str| {
+                attributes
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, value)| *value)
+                    .ok_or_else(|| malformed(&format!("<{}> without {}", name, key)))
+            };
+            match name {
+                "field" => content = Some(attribute("content")?),
+                "move" => moves.push(Move {
+                    from: parse_gravon_square(attribute("source")?)?,
+                    to: parse_gravon_square(attribute("target")?)?,
+                }),
+                "result" => {
+                    let winner = match attribute("winner")? {
+                        "1" => Some(Side::Red),
+                        "2" => Some(Side::Blue),
+                        _ => None,
+                    };
+                    outcome = match (attribute("type")?, winner) {
+                        (_, None) => Some(GameOutcome::Draw),
+                        ("1", Some(winner)) => Some(GameOutcome::FlagCaptured(winner)),
+                        ("2", Some(winner)) => Some(GameOutcome::NoMovablePieces(winner)),
+                        ("3", Some(winner)) => Some(GameOutcome::Resignation(winner)),
+                        ("4", Some(winner)) => Some(GameOutcome::Timeout(winner)),
+                        _ => None,
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        let content = content.ok_or_else(|| malformed("no <field>"))?;
+        if content.chars().count() != GRAVON_SIZE * GRAVON_SIZE {
+            return Err(malformed("<field> is not 100 squares"));
+        }
+        let mut board = Board::new(rules.layout.clone());
+        for (index, symbol) in content.chars().enumerate() {
+            if let Some((owner, piece_type)) = parse_gravon_symbol(symbol)? {
+                let (x, y) = from_gravon_index(index);
+                board.set(
+                    x,
+                    y,
+                    Some(Piece {
+                        id: Uuid::new_v4(),
+                        owner,
+                        piece_type,
+                        knowledge: Default::default(),
+                    }),
+                );
+            }
+        }
+
+        let zone_pieces = |primary: bool| {
+            let zone = rules.layout.setup_zone(primary);
+            let pieces = zone
+                .iter()
+                .map(|&(x, y)| board.get(x, y).unwrap().clone())
+                .collect::<Vec<_>>();
+            let owner = pieces
+                .iter()
+                .flatten()
+                .next()
+                .map(|piece| piece.owner.clone());
+            let pieces = pieces
+                .into_iter()
+                .map(|piece| piece.map(|piece| piece.piece_type))
+                .collect::<Vec<_>>();
+            (owner, Setup::new(&pieces, rules.layout.width))
+        };
+        let (primary_side, primary_setup) = zone_pieces(true);
+        let (_, secondary_setup) = zone_pieces(false);
+        let primary_side = primary_side.ok_or_else(|| malformed("no pieces in rows 1 to 4"))?;
+        let (red_setup, blue_setup) = match primary_side {
+            Side::Red => (primary_setup, secondary_setup),
+            Side::Blue => (secondary_setup, primary_setup),
+        };
+
+        Ok(Self {
+            rules,
+            primary_side,
+            red_setup,
+            blue_setup,
+            moves,
+            outcome,
+        })
+    }
+}
+
+const GRAVON_SIZE: usize = 10;
+
+/// Gravon pieces in the order of their letters, from `B` for red and from `N` for blue.
+const GRAVON_ORDER: [PieceType; 12] = [
+    PieceType::Flag,
+    PieceType::Spy,
+    PieceType::Scout,
+    PieceType::Miner,
+    PieceType::Sergeant,
+    PieceType::Lieutenant,
+    PieceType::Captain,
+    PieceType::Major,
+    PieceType::Colonel,
+    PieceType::General,
+    PieceType::Marshal,
+    PieceType::Bomb,
+];
+
+/// Gravon squares run from A1 to J10, turned around so A1 is our bottom right square.
+fn from_gravon_index(index: usize) -> (usize, usize) {
+    (
+        GRAVON_SIZE - 1 - index % GRAVON_SIZE,
+        GRAVON_SIZE - 1 - index / GRAVON_SIZE,
+    )
+}
+
+fn gravon_square((x, y): (usize, usize)) -> String {
+    let column = (b'A' + (GRAVON_SIZE - 1 - x) as u8) as char;
+    format!("{}{}", column, GRAVON_SIZE - y)
+}
+
+fn parse_gravon_square(square: &str) -> Result<(usize, usize), RecordError> {
+    let malformed = || RecordError::Malformed(format!("square {}", square));
+    let mut chars = square.chars();
+    let column = chars.next().ok_or_else(malformed)?;
+    let row = chars.as_str().parse::<usize>().map_err(|_| malformed())?;
+    if !('A'..='J').contains(&column) || !(1..=GRAVON_SIZE).contains(&row) {
+        return Err(malformed());
+    }
+    Ok((
+        GRAVON_SIZE - 1 - (column as u8 - b'A') as usize,
+        GRAVON_SIZE - row,
+    ))
+}
+
+fn gravon_symbol(owner: &Side, piece_type: &PieceType) -> Result<char, RecordError> {
+    let offset = GRAVON_ORDER
+        .iter()
+        .position(|t| t == piece_type)
+        .ok_or(RecordError::NotClassic)?;
+    let first = match owner {
+        Side::Red => b'B',
+        Side::Blue => b'N',
+    };
+    Ok((first + offset as u8) as char)
+}
+
+fn parse_gravon_symbol(symbol: char) -> Result<Option<(Side, PieceType)>, RecordError> {
+    let (owner, offset) = match symbol {
+        'A' | '_' => return Ok(None),
+        'B'..='M' => (Side::Red, symbol as u8 - b'B'),
+        'N'..='Y' => (Side::Blue, symbol as u8 - b'N'),
+        _ => return Err(RecordError::Malformed(format!("square '{}'", symbol))),
+    };
+    Ok(Some((owner, GRAVON_ORDER[offset as usize].clone())))
+}
+
+/// The name and attributes of every tag in `xml`, leaving out closing tags. Only as much XML as
+/// the Gravon records use: no entities, comments or CDATA.
+fn xml_tags(xml: &str) -> impl Iterator<Item = (&str, Vec<(&str, &str)>)> {
+    xml.split('<').skip(1).filter_map(|tag| {
+        let tag = tag.split('>').next()?.trim_end_matches('/').trim();
+        if tag.starts_with(['/', '?', '!']) {
+            return None;
+        }
+        let (name, mut rest) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+
+        let mut attributes = Vec::new();
+        while let Some((key, value)) = rest.split_once('=') {
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            let (value, tail) = value.strip_prefix(quote)?.split_once(quote)?;
+            attributes.push((key.trim(), value));
+            rest = tail;
+        }
+        Some((name, attributes))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::MoveResponse;
+
+    /// Red moves first from rows 1 to 4 with its Flag on A1, blue mirrors it from row 10.
+    const ARCHIVE_GAME: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<stratego>
+<game>
+<field content=\"BMFFGGHHIMMEEEEEGGHHIIJJKLFFMMDDDDCDDDDMAA__AA__AAAA__AA__AAPPPPOPPPPYUUVVWXRRYYYQQQQQSSTTNYRRSSTTUY\"/>
+<move id=\"1\" source=\"A4\" target=\"A6\"/>
+<move id=\"2\" source=\"A7\" target=\"A6\"/>
+<result type=\"3\" winner=\"2\"/>
+</game>
+</stratego>
+";
+
+    #[test]
+    fn gravon_round_trip() {
+        let record = GameRecord::from_gravon(ARCHIVE_GAME).unwrap();
+        assert_eq!(record.primary_side, Side::Red);
+        assert_eq!(record.outcome, Some(GameOutcome::Resignation(Side::Blue)));
+
+        let board = record.initial_board();
+        for side in [Side::Red, Side::Blue] {
+            let counts = board.count_for(&side);
+            assert_eq!(counts[&PieceType::Flag], 1);
+            assert_eq!(counts[&PieceType::Bomb], 6);
+        }
+        let at = |square| {
+            let (x, y) = parse_gravon_square(square).unwrap();
+            board
+                .get(x, y)
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .piece_type
+                .clone()
+        };
+        assert_eq!(at("A1"), PieceType::Flag);
+        assert_eq!(at("B1"), PieceType::Bomb);
+        assert_eq!(at("E4"), PieceType::Spy);
+
+        let (_, history) = record.replay().unwrap();
+        assert!(matches!(
+            history[1].response,
+            MoveResponse::AttackFailureMutual(..)
+        ));
+
+        assert_eq!(record.to_gravon().unwrap(), ARCHIVE_GAME);
+    }
+
+    #[test]
+    fn gravon_timeout() {
+        let xml = ARCHIVE_GAME.replace("type=\"3\"", "type=\"4\"");
+        let record = GameRecord::from_gravon(&xml).unwrap();
+        assert_eq!(record.outcome, Some(GameOutcome::Timeout(Side::Blue)));
+        assert_eq!(record.to_gravon().unwrap(), xml);
+    }
+
+    #[test]
+    fn xml_tags_with_multibyte_quote() {
+        let tags = xml_tags("<move source=\u{201c}A4\u{201c} target=\"A5\"/>").collect::<Vec<_>>();
+        assert_eq!(tags, [("move", vec![("source", "A4"), ("target", "A5")])]);
+    }
+}
@@ -1,18 +1,29 @@
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::stream::SplitStream;
+use futures::{SinkExt, StreamExt};
+use gloo_net::eventsource::futures::{EventSource, EventSourceSubscription};
 use gloo_net::http::Request;
+use gloo_net::websocket::futures::WebSocket;
+use gloo_net::websocket::Message;
 use uuid::Uuid;
 
+use crate::rules::RuleSet;
 use crate::{
-    game_logic::MoveResult, BoardState, GameInfo, InitSetupError, InitState, PieceMove, Side,
-    UserToken,
+    event::{ClientMessage, GameEvent},
+    game_logic::{GameOutcome, MoveResult}, BoardState, GameInfo, InitSetupError, InitState, PieceMove,
+    SidePreference, UserToken,
 };
 
 pub async fn create_game(game_info: GameInfo) -> anyhow::Result<Uuid> {
     let fetched = Request::post("/api/create_game")
         .json(&game_info)?
         .send()
-        .await?
-        .json()
         .await?;
+    let fetched = if fetched.ok() {
+        fetched.json().await?
+    } else {
+        anyhow::bail!(fetched.text().await?);
+    };
 
     Ok(fetched)
 }
@@ -42,14 +53,33 @@ pub async fn join_game(id: Uuid) -> anyhow::Result<UserToken> {
     Ok(fetched)
 }
 
-pub async fn join_random_game(side: Side) -> anyhow::Result<Uuid> {
-    let fetched = Request::get(
-        format!("/api/join_random/{}", side.to_string()).as_str(),
+/// Waits for a random opponent who wants to play by the same `rules`.
+pub async fn join_random_game(
+    side: SidePreference,
+    ticket: Uuid,
+    rules: &RuleSet,
+) -> anyhow::Result<(Uuid, UserToken)> {
+    let fetched = Request::post(
+        format!("/api/join_random/{}/{}", side.to_string(), ticket.to_string()).as_str(),
     )
+    .json(rules)?
     .send()
-    .await?
-    .json()
     .await?;
+    let fetched = if fetched.ok() {
+        fetched.json().await?
+    } else {
+        anyhow::bail!(fetched.text().await?);
+    };
+
+    Ok(fetched)
+}
+
+pub async fn cancel_random_game(ticket: Uuid) -> anyhow::Result<bool> {
+    let fetched = Request::delete(format!("/api/join_random/{}", ticket.to_string()).as_str())
+        .send()
+        .await?
+        .json()
+        .await?;
 
     Ok(fetched)
 }
@@ -74,18 +104,25 @@ pub async fn get_game_state(game_id: Uuid, user_id: Uuid) -> anyhow::Result<Boar
     Ok(fetched)
 }
 
-pub async fn get_game_state_changed(game_id: Uuid, user_id: Uuid) -> anyhow::Result<bool> {
+/// Waits for the game to get past version `since`. Gives the state unchanged if that takes
+/// too long, so the caller should compare versions and ask again.
+pub async fn get_game_state_changed(
+    game_id: Uuid,
+    user_id: Uuid,
+    since: u64,
+) -> anyhow::Result<BoardState> {
     let fetched = Request::get(
         format!(
-            "/api/{}/game_state_changed/{}",
+            "/api/{}/game_state_changed/{}?since={}",
             game_id.to_string(),
-            user_id.to_string()
+            user_id.to_string(),
+            since
         )
         .as_str(),
     )
     .send()
     .await?;
-    let fetched = if fetched.ok() {
+    let fetched: BoardState = if fetched.ok() {
         fetched.json().await?
     } else {
         anyhow::bail!(fetched.text().await?);
@@ -118,3 +155,131 @@ pub async fn init_setup(id: Uuid, init_state: InitState) -> anyhow::Result<()> {
         Err(fetched.json::<InitSetupError>().await?.into())
     }
 }
+
+pub async fn resign(game_id: Uuid, user_id: Uuid) -> anyhow::Result<GameOutcome> {
+    let fetched = Request::put(
+        format!(
+            "/api/{}/resign/{}",
+            game_id.to_string(),
+            user_id.to_string()
+        )
+        .as_str(),
+    )
+    .send()
+    .await?;
+    let fetched = if fetched.ok() {
+        fetched.json().await?
+    } else {
+        anyhow::bail!(fetched.text().await?);
+    };
+
+    Ok(fetched)
+}
+
+/// The sending end of a game channel. Messages are queued and sent in order.
+#[derive(Clone)]
+pub struct ChannelSender(UnboundedSender<ClientMessage>);
+
+impl ChannelSender {
+    pub fn send(&self, message: ClientMessage) -> anyhow::Result<()> {
+        self.0
+            .unbounded_send(message)
+            .map_err(|_| anyhow::anyhow!("Game channel closed"))
+    }
+}
+
+/// The receiving end of a game channel.
+pub struct ChannelReceiver(SplitStream<WebSocket>);
+
+impl ChannelReceiver {
+    /// The next event, or `None` once the channel is closed.
+    pub async fn next(&mut self) -> Option<anyhow::Result<GameEvent>> {
+        loop {
+            return Some(match self.0.next().await? {
+                Ok(Message::Text(text)) => serde_json::from_str(&text).map_err(Into::into),
+                Ok(Message::Bytes(_)) => continue,
+                Err(err) => Err(anyhow::anyhow!(err.to_string())),
+            });
+        }
+    }
+}
+
+/// Opens the channel of `game_id` for `user_id`, which starts with a `GameEvent::State`.
+pub fn open_channel(
+    game_id: Uuid,
+    user_id: Uuid,
+) -> anyhow::Result<(ChannelSender, ChannelReceiver)> {
+    let location = web_sys::window()
+        .ok_or_else(|| anyhow::anyhow!("No window"))?
+        .location();
+    let protocol = location.protocol().map_err(|_| anyhow::anyhow!("No location"))?;
+    let host = location.host().map_err(|_| anyhow::anyhow!("No location"))?;
+    let scheme = if protocol == "https:" { "wss" } else { "ws" };
+
+    let url = format!("{}://{}/api/{}/channel/{}", scheme, host, game_id, user_id);
+    let (mut write, read) = WebSocket::open(&url)?.split();
+
+    let (sender, mut outgoing) = mpsc::unbounded::<ClientMessage>();
+    wasm_bindgen_futures::spawn_local(async move {
+        while let Some(message) = outgoing.next().await {
+            let text = serde_json::to_string(&message).expect("messages always serialize");
+            if write.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok((ChannelSender(sender), ChannelReceiver(read)))
+}
+
+/// A subscription to the events of a game as Server-Sent Events, for where WebSockets are not
+/// available. The browser reconnects on its own, resuming after the last event seen.
+pub struct EventSubscriber {
+    _source: EventSource,
+    events: EventSourceSubscription,
+    last_event_id: Option<u64>,
+}
+
+impl EventSubscriber {
+    /// The next event, or `None` once the subscription is closed. Errors do not end it, the
+    /// browser trying to reconnect after them.
+    pub async fn next(&mut self) -> Option<anyhow::Result<GameEvent>> {
+        let (_, message) = match self.events.next().await? {
+            Ok(message) => message,
+            Err(err) => return Some(Err(anyhow::anyhow!(err.to_string()))),
+        };
+        if let Ok(id) = message.last_event_id().parse() {
+            self.last_event_id = Some(id);
+        }
+
+        let data = message.data().as_string().unwrap_or_default();
+        Some(serde_json::from_str(&data).map_err(Into::into))
+    }
+
+    /// The id of the last event received, to resume from with `subscribe_events`.
+    pub fn last_event_id(&self) -> Option<u64> {
+        self.last_event_id
+    }
+}
+
+/// Subscribes to the events of `game_id` for `user_id`, from after `last_event_id` if given and
+/// otherwise from a `GameEvent::State`.
+pub fn subscribe_events(
+    game_id: Uuid,
+    user_id: Uuid,
+    last_event_id: Option<u64>,
+) -> anyhow::Result<EventSubscriber> {
+    let mut url = format!("/api/{}/events/{}", game_id, user_id);
+    if let Some(last_event_id) = last_event_id {
+        url.push_str(&format!("?last_event_id={}", last_event_id));
+    }
+
+    let mut source = EventSource::new(&url)?;
+    let events = source.subscribe("message")?;
+
+    Ok(EventSubscriber {
+        _source: source,
+        events,
+        last_event_id,
+    })
+}
@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, EnumString};
+
+use crate::bitboard::MAX_SQUARES;
+use crate::catalog::{self, Movement, PieceDefinition};
+use crate::layout::BoardLayout;
+use crate::setup::CUSTOM_SYMBOLS;
+use crate::PieceType;
+
+/// The rules a game is played by, chosen when it is created.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct RuleSet {
+    pub layout: BoardLayout,
+    /// The catalog of pieces every side starts with.
+    pub pieces: Vec<PieceDefinition>,
+    pub combat: CombatTable,
+    /// Whether a piece moving in a line may move several squares and attack at the end of the
+    /// same move. Without it such a piece only attacks what is right next to it.
+    pub scout_move_and_attack: bool,
+    /// Whether the two-square and more-squares rules forbid repeating moves.
+    pub repetition_rules: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Variant::Classic.rules()
+    }
+}
+
+impl RuleSet {
+    pub fn piece(&self, piece_type: &PieceType) -> Option<&PieceDefinition> {
+        self.pieces
+            .iter()
+            .find(|piece| &piece.piece_type == piece_type)
+    }
+
+    pub fn piece_count(&self, piece_type: &PieceType) -> usize {
+        self.piece(piece_type).map_or(0, |piece| piece.count)
+    }
+
+    /// How many pieces every side starts with.
+    pub fn army_size(&self) -> usize {
+        self.pieces.iter().map(|piece| piece.count).sum()
+    }
+
+    /// How `piece_type` moves. Types outside the catalog, like hidden pieces, take single steps.
+    pub fn movement(&self, piece_type: &PieceType) -> Movement {
+        self.piece(piece_type)
+            .map_or(Movement::Step, |piece| piece.movement)
+    }
+
+    /// How an attack ends: by the attacker's special attacks if one applies, otherwise by rank.
+    /// Types outside the catalog rank below every other.
+    pub fn resolve(&self, attacker: &PieceType, defender: &PieceType) -> Combat {
+        let attacking = self.piece(attacker);
+        let special =
+            attacking.and_then(|piece| piece.attacks.iter().find(|(target, _)| target == defender));
+        let rank = |piece_type| self.piece(piece_type).map(|piece| piece.rank);
+
+        let combat = match special {
+            Some((_, combat)) => *combat,
+            None if rank(attacker) == rank(defender) => self.combat.ties,
+            None if rank(attacker) > rank(defender) => Combat::AttackerWins,
+            None => Combat::DefenderWins,
+        };
+
+        if combat == Combat::DefenderWins
+            && self.combat.single_use_bombs
+            && defender == &PieceType::Bomb
+        {
+            Combat::BothLose
+        } else {
+            combat
+        }
+    }
+
+    /// Whether a game can be played by these rules: the layout is valid and fits a `BitBoard`,
+    /// both setup zones are the same size, no type is in the catalog twice and every army fits
+    /// into its zone with a flag. Custom types need a symbol in the setup notation to be stored.
+    pub fn is_playable(&self) -> bool {
+        let [primary, secondary] = &self.layout.setup_zones;
+        let distinct = self.pieces.iter().enumerate().all(|(i, piece)| {
+            self.pieces[..i]
+                .iter()
+                .all(|other| other.piece_type != piece.piece_type)
+        });
+
+        self.layout.is_valid()
+            && self.layout.size() <= MAX_SQUARES
+            && primary.len() == secondary.len()
+            && distinct
+            && self.piece(&PieceType::Unknown).is_none()
+            && self.pieces.iter().all(|piece| match piece.piece_type {
+                PieceType::Custom(n) => n < CUSTOM_SYMBOLS,
+                _ => true,
+            })
+            && self.piece_count(&PieceType::Flag) == 1
+            && self.army_size() <= primary.len()
+    }
+}
+
+/// How an attack ends.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Combat {
+    AttackerWins,
+    DefenderWins,
+    BothLose,
+}
+
+/// The parts of combat that do not belong to a single piece. Ranks and special attacks, such as
+/// the Spy beating the Marshal, are in the piece catalog.
+///
+/// The classic table lets equal ranks take each other out and a Bomb stay after taking out an
+/// attacker.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct CombatTable {
+    pub ties: Combat,
+    /// Whether a Bomb is taken off the board along with the attacker it takes out.
+    pub single_use_bombs: bool,
+}
+
+impl Default for CombatTable {
+    fn default() -> Self {
+        Self {
+            ties: Combat::BothLose,
+            single_use_bombs: false,
+        }
+    }
+}
+
+/// The rule sets games can be created with.
+#[derive(
+    Deserialize, Serialize, Clone, PartialEq, Debug, Default, Display, EnumIter, EnumString,
+)]
+pub enum Variant {
+    #[default]
+    Classic,
+    /// Eight pieces each on the classic board.
+    Barrage,
+    /// Ten pieces each on an eight by eight board.
+    Duel,
+    /// Twenty pieces each, set up in two rows.
+    #[strum(to_string = "Ultimate Lightning", serialize = "UltimateLightning")]
+    UltimateLightning,
+}
+
+impl Variant {
+    pub fn rules(&self) -> RuleSet {
+        let pieces = |counts: &[(PieceType, usize)]| {
+            counts
+                .iter()
+                .map(|(piece_type, count)| PieceDefinition::classic(piece_type.clone(), *count))
+                .collect()
+        };
+
+        match self {
+            Variant::Classic => RuleSet {
+                layout: BoardLayout::classic(),
+                pieces: catalog::classic(),
+                combat: CombatTable::default(),
+                scout_move_and_attack: true,
+                repetition_rules: true,
+            },
+            Variant::Barrage => RuleSet {
+                pieces: pieces(&[
+                    (PieceType::Flag, 1),
+                    (PieceType::Bomb, 1),
+                    (PieceType::Spy, 1),
+                    (PieceType::Scout, 2),
+                    (PieceType::Miner, 1),
+                    (PieceType::General, 1),
+                    (PieceType::Marshal, 1),
+                ]),
+                ..Variant::Classic.rules()
+            },
+            Variant::Duel => RuleSet {
+                layout: BoardLayout::rows(8, 8, 3, vec![(2, 3), (2, 4), (5, 3), (5, 4)]),
+                pieces: pieces(&[
+                    (PieceType::Flag, 1),
+                    (PieceType::Bomb, 2),
+                    (PieceType::Spy, 1),
+                    (PieceType::Scout, 2),
+                    (PieceType::Miner, 2),
+                    (PieceType::General, 1),
+                    (PieceType::Marshal, 1),
+                ]),
+                ..Variant::Classic.rules()
+            },
+            Variant::UltimateLightning => RuleSet {
+                layout: BoardLayout::rows(10, 10, 2, BoardLayout::classic().impassable),
+                pieces: pieces(&[
+                    (PieceType::Flag, 1),
+                    (PieceType::Bomb, 2),
+                    (PieceType::Spy, 1),
+                    (PieceType::Scout, 4),
+                    (PieceType::Miner, 2),
+                    (PieceType::Sergeant, 2),
+                    (PieceType::Lieutenant, 2),
+                    (PieceType::Captain, 2),
+                    (PieceType::Major, 1),
+                    (PieceType::Colonel, 1),
+                    (PieceType::General, 1),
+                    (PieceType::Marshal, 1),
+                ]),
+                ..Variant::Classic.rules()
+            },
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::PieceType;
+
+/// How many custom types the notation has a symbol for, `a` to `z`.
+pub const CUSTOM_SYMBOLS: u8 = 26;
+
+/// A setup in a compact notation, one character per square: `F` for the Flag, `B` for a Bomb,
+/// `1` (the Marshal) to `9` (a Scout), `S` for the Spy, `a` to `z` for custom pieces and `.` for
+/// an empty square. Rows are separated by `/` and follow the setup zone, front row first.
+///
+/// A zone of two rows of three could read `9.8/BFB`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Setup {
+    pub rows: Vec<Vec<Option<PieceType>>>,
+}
+
+impl Setup {
+    /// Splits `pieces`, in the order of a setup zone, into rows of `row_length`.
+    pub fn new(pieces: &[Option<PieceType>], row_length: usize) -> Self {
+        Self {
+            rows: pieces
+                .chunks(row_length.max(1))
+                .map(|row| row.to_vec())
+                .collect(),
+        }
+    }
+
+    /// Every square in the order of the setup zone, as sent in `InitState::pieces`.
+    pub fn pieces(&self) -> Vec<Option<PieceType>> {
+        self.rows.iter().flatten().cloned().collect()
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SetupParseError {
+    #[error("Unknown Piece '{0}'")]
+    UnknownSymbol(char),
+    #[error("Row {row} has {length} squares instead of {expected}")]
+    RaggedRow {
+        row: usize,
+        length: usize,
+        expected: usize,
+    },
+}
+
+fn symbol(piece_type: &PieceType) -> char {
+    match piece_type {
+        PieceType::Flag => 'F',
+        PieceType::Bomb => 'B',
+        PieceType::Marshal => '1',
+        PieceType::General => '2',
+        PieceType::Colonel => '3',
+        PieceType::Major => '4',
+        PieceType::Captain => '5',
+        PieceType::Lieutenant => '6',
+        PieceType::Sergeant => '7',
+        PieceType::Miner => '8',
+        PieceType::Scout => '9',
+        PieceType::Spy => 'S',
+        PieceType::Custom(n) if *n < CUSTOM_SYMBOLS => (b'a' + n) as char,
+        PieceType::Custom(_) | PieceType::Unknown => '?',
+    }
+}
+
+fn piece_type(symbol: char) -> Result<PieceType, SetupParseError> {
+    Ok(match symbol {
+        'F' => PieceType::Flag,
+        'B' => PieceType::Bomb,
+        '1' => PieceType::Marshal,
+        '2' => PieceType::General,
+        '3' => PieceType::Colonel,
+        '4' => PieceType::Major,
+        '5' => PieceType::Captain,
+        '6' => PieceType::Lieutenant,
+        '7' => PieceType::Sergeant,
+        '8' => PieceType::Miner,
+        '9' => PieceType::Scout,
+        'S' => PieceType::Spy,
+        'a'..='z' => PieceType::Custom(symbol as u8 - b'a'),
+        _ => return Err(SetupParseError::UnknownSymbol(symbol)),
+    })
+}
+
+impl fmt::Display for Setup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, row) in self.rows.iter().enumerate() {
+            if i > 0 {
+                write!(f, "/")?;
+            }
+            for square in row {
+                write!(f, "{}", square.as_ref().map_or('.', symbol))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Setup {
+    type Err = SetupParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let rows: Vec<Vec<_>> = s
+            .split('/')
+            .map(|row| {
+                row.trim()
+                    .chars()
+                    .map(|c| match c {
+                        '.' => Ok(None),
+                        c => piece_type(c).map(Some),
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+        let expected = rows[0].len();
+        if let Some((row, length)) = rows
+            .iter()
+            .map(Vec::len)
+            .enumerate()
+            .find(|&(_, length)| length != expected)
+        {
+            return Err(SetupParseError::RaggedRow {
+                row,
+                length,
+                expected,
+            });
+        }
+        Ok(Self { rows })
+    }
+}
+
+impl Serialize for Setup {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Setup {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let notation = "9.8/BFB";
+        let setup = notation.parse::<Setup>().unwrap();
+        assert_eq!(
+            setup.pieces(),
+            vec![
+                Some(PieceType::Scout),
+                None,
+                Some(PieceType::Miner),
+                Some(PieceType::Bomb),
+                Some(PieceType::Flag),
+                Some(PieceType::Bomb),
+            ]
+        );
+        assert_eq!(setup.to_string(), notation);
+        assert_eq!(Setup::new(&setup.pieces(), 3), setup);
+        assert_eq!("".parse::<Setup>(), Ok(Setup::default()));
+    }
+
+    #[test]
+    fn custom_symbols() {
+        let setup = "az/S1".parse::<Setup>().unwrap();
+        assert_eq!(setup.rows[0][0], Some(PieceType::Custom(0)));
+        assert_eq!(
+            setup.rows[0][1],
+            Some(PieceType::Custom(CUSTOM_SYMBOLS - 1))
+        );
+        assert_eq!(setup.to_string(), "az/S1");
+        assert_eq!(symbol(&PieceType::Custom(CUSTOM_SYMBOLS)), '?');
+    }
+
+    #[test]
+    fn rejects_bad_notation() {
+        assert_eq!(
+            "9X8".parse::<Setup>(),
+            Err(SetupParseError::UnknownSymbol('X'))
+        );
+        assert_eq!(
+            "9?8".parse::<Setup>(),
+            Err(SetupParseError::UnknownSymbol('?'))
+        );
+        assert_eq!(
+            "FBB/BB...".parse::<Setup>(),
+            Err(SetupParseError::RaggedRow {
+                row: 1,
+                length: 5,
+                expected: 3,
+            })
+        );
+    }
+}
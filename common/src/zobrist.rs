@@ -0,0 +1,43 @@
+use crate::{Piece, PieceType, Side};
+
+/// Xored into a position's hash when Blue is to move.
+pub const BLUE_TO_MOVE: u64 = 0x9e6c_63d0_676a_9a99;
+
+/// The Zobrist key of `piece` standing on the square kept at `index` in `Board::squares`,
+/// covering its type, its owner and what its opponent knows about it. A position hashes to the
+/// keys of all its pieces xored together, so a move only xors out and in the squares it changes.
+pub fn piece_key(index: usize, piece: &Piece) -> u64 {
+    let piece_type = match piece.piece_type {
+        PieceType::Bomb => 0,
+        PieceType::Marshal => 1,
+        PieceType::General => 2,
+        PieceType::Colonel => 3,
+        PieceType::Major => 4,
+        PieceType::Captain => 5,
+        PieceType::Lieutenant => 6,
+        PieceType::Sergeant => 7,
+        PieceType::Miner => 8,
+        PieceType::Scout => 9,
+        PieceType::Spy => 10,
+        PieceType::Flag => 11,
+        PieceType::Unknown => 12,
+        PieceType::Custom(n) => 13 + n as u64,
+    };
+    let owner = match piece.owner {
+        Side::Red => 0,
+        Side::Blue => 1,
+    };
+    let knowledge = piece.knowledge.revealed as u64
+        | (piece.knowledge.moved as u64) << 1
+        | (piece.knowledge.scouted as u64) << 2;
+
+    mix((index as u64) << 16 | piece_type << 4 | owner << 3 | knowledge)
+}
+
+/// SplitMix64, standing in for a table of random keys.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
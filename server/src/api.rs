@@ -1,28 +1,51 @@
 use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
+use common::event::GameEvent;
 use common::game_logic;
+use common::game_logic::GameOutcome;
+use common::game_logic::Move;
 use common::game_logic::MoveError;
+use common::game_logic::MoveRecord;
 use common::game_logic::MoveResult;
+use common::game_logic::PlayedMove;
+use common::record::GameRecord;
+use common::rules::RuleSet;
+use common::setup::Setup;
 use common::Board;
 use common::BoardState;
 use common::GameInfo;
+use common::GamePhase;
 use common::InitSetupError;
 use common::InitState;
 use common::Piece;
 use common::PieceMove;
 use common::PieceType;
 use common::Side;
+use common::SpectatorView;
 use common::UserToken;
+use rocket::http::ContentType;
 use rocket::http::Status;
 use rocket::response::status;
 use rocket::tokio::sync::broadcast;
-use rocket::tokio::sync::broadcast::Receiver;
 use rocket::tokio::sync::broadcast::Sender;
+use rocket::tokio::sync::watch;
+use rocket::tokio::time;
 use rocket::{serde::json::Json, tokio::sync::Mutex, Route, State};
-use strum::IntoEnumIterator;
 use uuid::Uuid;
 
-use crate::util::SideGard;
+use crate::bot;
+use crate::matchmaking::Enqueued;
+use crate::matchmaking::MatchError;
+use crate::matchmaking::MatchQueue;
+use crate::storage::GameStorage;
+use crate::storage::StorageWriter;
+use crate::storage::StoredGame;
+use crate::util::SidePreferenceGard;
 use crate::util::UuidGard;
 
 pub fn api() -> Vec<Route> {
@@ -34,82 +57,546 @@ pub fn api() -> Vec<Route> {
         get_game_state_changed,
         move_piece,
         init_setup,
-        join_random_game
+        resign,
+        export_record,
+        join_random_game,
+        cancel_random_game
     ]
 }
 
+/// How long `get_game_state_changed` waits for a change before giving the unchanged state.
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many of the latest events a game keeps for clients resuming its event stream.
+const EVENT_LOG_LENGTH: usize = 256;
+
+#[derive(Clone)]
 pub struct GameStoreState {
-    games: Mutex<HashMap<Uuid, GameState>>,
-    bot_games: Mutex<Vec<Uuid>>,
-    changed_games: Sender<Uuid>,
-    _cgr: Receiver<Uuid>,
+    pub games: Arc<Mutex<HashMap<Uuid, GameState>>>,
+    bot_games: Arc<Mutex<Vec<Uuid>>>,
+    pub storage: StorageWriter,
 }
 
 pub struct GameState {
     pub board: Board,
     pub primary_side: Side,
-    pub clients: HashMap<Uuid, (Option<Side>, Receiver<Uuid>)>,
+    pub spectator_view: SpectatorView,
+    pub clients: HashMap<Uuid, Option<Side>>,
     pub active_side: Side,
+    pub phase: GamePhase,
     pub ready: HashMap<Side, bool>,
+    pub outcome: Option<GameOutcome>,
+    pub history: Vec<PlayedMove>,
+    pub rules: RuleSet,
+    pub training: bool,
+    /// Each side's setup as it was sent, kept for the game record.
+    pub setups: HashMap<Side, Vec<Option<PieceType>>>,
+    /// What happens in the game, with the board unhidden, see `Viewer::event`. Events are
+    /// numbered from 1, or on from the time of the restart for a restored game.
+    pub events: Sender<(u64, GameEvent)>,
+    /// The latest events, for clients to catch up on what they missed.
+    pub event_log: VecDeque<(u64, GameEvent)>,
+    pub last_event_id: u64,
+    /// Counts the changes to the game, so waiting on it does not need the store locked.
+    pub version: watch::Sender<u64>,
+    /// When each user, by token, last had a chat message passed on.
+    pub last_chat: HashMap<Uuid, Instant>,
+}
+
+/// How a client of a game sees it.
+#[derive(Clone)]
+pub struct Viewer {
+    pub side: Option<Side>,
+    pub spectator_view: SpectatorView,
+}
+
+impl Viewer {
+    /// `board` with what this client may not see hidden.
+    pub fn board(&self, board: &Board) -> Board {
+        match (&self.side, &self.spectator_view) {
+            (Some(side), _) => board.view_for(Some(side)),
+            (None, SpectatorView::Hidden) => board.view_for(None),
+            (None, SpectatorView::Side(side)) => board.view_for(Some(side)),
+            (None, SpectatorView::Full) => board.clone(),
+        }
+    }
+    pub fn event(&self, event: GameEvent) -> GameEvent {
+        match event {
+            GameEvent::SetupDone { side, board, phase } => GameEvent::SetupDone {
+                side,
+                board: self.board(&board),
+                phase,
+            },
+            GameEvent::MoveMade { played, board } => GameEvent::MoveMade {
+                played,
+                board: self.board(&board),
+            },
+            event => event,
+        }
+    }
 }
 
 impl GameState {
-    pub fn new(primary_side: Side) -> Self {
+    pub fn new(game_info: GameInfo) -> Self {
+        let primary_side = game_info.primary_side;
         Self {
-            board: Board::new(),
+            board: Board::new(game_info.rules.layout.clone()),
             primary_side: primary_side.clone(),
+            spectator_view: game_info.spectator_view,
             clients: HashMap::new(),
             active_side: primary_side,
+            phase: GamePhase::WaitingForPlayers,
             ready: HashMap::new(),
+            outcome: None,
+            history: Vec::new(),
+            rules: game_info.rules,
+            training: game_info.training,
+            setups: HashMap::new(),
+            events: broadcast::channel(64).0,
+            event_log: VecDeque::new(),
+            last_event_id: 0,
+            version: watch::channel(0).0,
+            last_chat: HashMap::new(),
         }
     }
+    /// Plays a stored game back to where it was, numbering later events from `first_event_id`.
+    pub fn restore(stored: &StoredGame, first_event_id: u64) -> anyhow::Result<Self> {
+        let mut game = Self::new(stored.info.clone());
+        game.clients.extend(stored.players.iter().cloned());
+        game.seats_filled(stored.info.vs_bot);
+
+        let token = |game: &Self, side: &Side| {
+            game.clients
+                .iter()
+                .find(|(_, player)| player.as_ref() == Some(side))
+                .map(|(token, _)| *token)
+                .ok_or_else(|| anyhow::anyhow!("No player for {}", side))
+        };
+        for side in [game.primary_side.clone(), !game.primary_side.clone()] {
+            if let Some(setup) = stored.setups.get(&side) {
+                game.init_setup(&InitState {
+                    access_token: token(&game, &side)?,
+                    pieces: Vec::new(),
+                    setup: Some(setup.clone()),
+                })?;
+            }
+        }
+        for piece_move in &stored.moves {
+            let (x, y) = piece_move.from;
+            let Some(Some(piece)) = game.board.get(x, y).cloned() else {
+                return Err(MoveError::PieceNotFound(x, y).into());
+            };
+            game.move_piece(&PieceMove {
+                access_token: token(&game, &piece.owner)?,
+                piece_id: piece.id,
+                x: piece_move.to.0,
+                y: piece_move.to.1,
+            })?;
+        }
+
+        if let (None, Some(outcome)) = (&game.outcome, &stored.outcome) {
+            game.outcome = Some(outcome.clone());
+            game.phase = GamePhase::Finished;
+        }
+        // the events of the replay were never sent, and ids a client has from before the restart
+        // must not be taken for later ones, so resuming from those starts over from the state
+        game.event_log.clear();
+        game.last_event_id = first_event_id;
+        Ok(game)
+    }
     pub fn has_primary(&self) -> bool {
         let primary_side = self.primary_side.clone();
         self.clients
             .values()
-            .find(move |&(side, _)| side == &Some(primary_side.clone()))
+            .find(move |&side| side == &Some(primary_side.clone()))
             .is_some()
     }
     pub fn has_secondary(&self) -> bool {
         let secondary_side = !self.primary_side.clone();
         self.clients
             .values()
-            .find(move |&(side, _)| side == &Some(secondary_side.clone()))
+            .find(move |&side| side == &Some(secondary_side.clone()))
             .is_some()
     }
+    /// Logs `event` and sends it to every open channel of the game, if there are any.
+    pub fn emit(&mut self, event: GameEvent) {
+        self.last_event_id += 1;
+        if self.event_log.len() == EVENT_LOG_LENGTH {
+            self.event_log.pop_front();
+        }
+        self.event_log.push_back((self.last_event_id, event.clone()));
+        let _ = self.events.send((self.last_event_id, event));
+    }
+    /// The events after `last_event_id`, `None` if some are no longer logged.
+    pub fn events_since(&self, last_event_id: u64) -> Option<Vec<(u64, GameEvent)>> {
+        let first = self.last_event_id - self.event_log.len() as u64;
+        if last_event_id < first || last_event_id > self.last_event_id {
+            return None;
+        }
+        let missed = (last_event_id - first) as usize;
+        Some(self.event_log.iter().skip(missed).cloned().collect())
+    }
+    /// Bumps the version, waking everyone waiting on the game, and emits `event`.
+    pub fn changed(&mut self, event: GameEvent) {
+        self.version.send_modify(|version| *version += 1);
+        self.emit(event);
+    }
     pub fn ready(&self) -> bool {
         self.ready.get(&Side::Red).unwrap_or(&false) & self.ready.get(&Side::Blue).unwrap_or(&false)
     }
+    /// Leaves `WaitingForPlayers` once both seats are taken, `vs_bot` games only needing the
+    /// primary.
+    pub fn seats_filled(&mut self, vs_bot: bool) {
+        if self.phase == GamePhase::WaitingForPlayers
+            && self.has_primary()
+            && (vs_bot || self.has_secondary())
+        {
+            self.phase = GamePhase::Setup;
+        }
+    }
+    pub fn init_setup(&mut self, init_state: &InitState) -> Result<(), InitSetupError> {
+        let side = match self.clients.get(&init_state.access_token) {
+            Some(Some(side)) => side.clone(),
+            _ => return Err(InitSetupError::InvalidAccess),
+        };
+
+        match self.phase {
+            GamePhase::WaitingForPlayers | GamePhase::Setup => {}
+            _ => return Err(InitSetupError::OutOfPhase(self.phase.clone())),
+        }
+        if *self.ready.get(&side).unwrap_or(&false) {
+            return Err(InitSetupError::AlreadySetup);
+        }
+
+        let zone = self.board.layout.setup_zone(side == self.primary_side).to_vec();
+        let pieces = init_state.setup_pieces();
+        if pieces.len() != zone.len() {
+            return Err(InitSetupError::IncorrectPieceCount);
+        }
+
+        let mut piece_count = HashMap::new();
+
+        for t in pieces.iter().flatten() {
+            let c = piece_count.get(&t).unwrap_or(&0);
+            piece_count.insert(t, *c + 1);
+        }
+
+        // every type placed has to be in the catalog, and every one in it placed in full
+        let mut correct_piece_count = piece_count
+            .keys()
+            .all(|piece_type| self.rules.piece(piece_type).is_some());
+
+        for piece in &self.rules.pieces {
+            correct_piece_count &=
+                *piece_count.get(&piece.piece_type).unwrap_or(&0) == piece.count;
+        }
+
+        if !correct_piece_count {
+            return Err(InitSetupError::IncorrectPieceCount);
+        }
+
+        for ((x, y), piece_type) in zone.into_iter().zip(&pieces) {
+            let Some(piece_type) = piece_type else {
+                continue;
+            };
+            self.board.set(
+                x,
+                y,
+                Some(Piece {
+                    id: Uuid::new_v4(),
+                    owner: side.clone(),
+                    piece_type: piece_type.clone(),
+                    knowledge: Default::default(),
+                }),
+            );
+        }
+        self.setups.insert(side.clone(), pieces);
+        self.ready.insert(side.clone(), true);
+
+        if self.ready() && self.phase == GamePhase::Setup {
+            self.phase = GamePhase::Playing;
+        }
+        self.changed(GameEvent::SetupDone {
+            side,
+            board: self.board.clone(),
+            phase: self.phase.clone(),
+        });
+
+        Ok(())
+    }
+    pub fn move_piece(&mut self, piece_move: &PieceMove) -> MoveResult {
+        match self.phase {
+            GamePhase::Playing => {}
+            GamePhase::Finished => return Err(MoveError::GameOver),
+            _ => return Err(MoveError::OutOfPhase(self.phase.clone())),
+        }
+
+        let side = match self.clients.get(&piece_move.access_token) {
+            Some(Some(side)) => side.clone(),
+            _ => return Err(MoveError::InvalidAccess),
+        };
+        if side != self.active_side {
+            return Err(MoveError::NotYourTurn);
+        }
+
+        let position = self
+            .board
+            .find(piece_move.piece_id)
+            .ok_or(MoveError::PieceDoesNotExist(piece_move.piece_id))?;
+        if let Some(Some(piece)) = self.board.get(position.0, position.1) {
+            if piece.owner != side {
+                return Err(MoveError::PieceDoesNotExist(piece_move.piece_id));
+            }
+        }
+        let played_move = Move {
+            from: position,
+            to: (piece_move.x, piece_move.y),
+        };
+        // the repetition rules only apply to moves that are otherwise valid
+        let record = MoveRecord::new(&self.board, &self.rules, &played_move)?;
+        game_logic::check_repetition(&self.board, &self.rules, &self.history, &side, &played_move)?;
+
+        record.apply(&mut self.board);
+        let res = record.response;
+        let played = PlayedMove {
+            side: side.clone(),
+            piece_move: played_move,
+            response: res.clone(),
+        };
+        self.history.push(played.clone());
+
+        self.active_side = !side;
+        self.outcome = game_logic::game_outcome(&self.board, &self.rules, &self.history, &self.active_side);
+        self.changed(GameEvent::MoveMade {
+            played,
+            board: self.board.clone(),
+        });
+        if let Some(outcome) = &self.outcome {
+            self.phase = GamePhase::Finished;
+            self.changed(GameEvent::GameOver(outcome.clone()));
+        }
+
+        Ok(res)
+    }
+    /// The record of the game so far, once both sides have set up.
+    pub fn record(&self) -> Option<GameRecord> {
+        let setup = |side| {
+            let pieces = self.setups.get(&side)?;
+            Some(Setup::new(pieces, self.rules.layout.width))
+        };
+
+        Some(GameRecord {
+            rules: self.rules.clone(),
+            primary_side: self.primary_side.clone(),
+            red_setup: setup(Side::Red)?,
+            blue_setup: setup(Side::Blue)?,
+            moves: self.history.iter().map(|played| played.piece_move).collect(),
+            outcome: self.outcome.clone(),
+        })
+    }
+    /// How `user_token` sees the game, `None` if it is not a client of it.
+    pub fn viewer(&self, user_token: &Uuid) -> Option<Viewer> {
+        let side = self.clients.get(user_token)?;
+        Some(Viewer {
+            side: side.clone(),
+            spectator_view: self.spectator_view.clone(),
+        })
+    }
+    /// The state sent to a client, with the board hidden according to its side.
+    /// Returns `None` if `user_token` is not a client of this game.
+    pub fn board_state_for(&self, user_token: &Uuid) -> Option<BoardState> {
+        let viewer = self.viewer(user_token)?;
+
+        Some(BoardState {
+            board: viewer.board(&self.board),
+            version: *self.version.borrow(),
+            active_side: self.active_side.clone(),
+            phase: self.phase.clone(),
+            outcome: self.outcome.clone(),
+            history: self.history.clone(),
+            rules: self.rules.clone(),
+            training: self.training,
+        })
+    }
 }
 
-impl Default for GameStoreState {
-    fn default() -> Self {
-        let (send, recv) = broadcast::channel(16);
-        Self {
+impl GameStoreState {
+    /// A store holding every unfinished game `storage` kept, their bots playing on. Finished
+    /// games stay in `storage` only, for their records.
+    pub async fn load(storage: Arc<dyn GameStorage>) -> anyhow::Result<Self> {
+        let stored_games = storage.load()?;
+        let game_states = Self {
             games: Default::default(),
             bot_games: Default::default(),
-            changed_games: send,
-            _cgr: recv,
+            storage: StorageWriter::spawn(storage),
+        };
+        // milliseconds since the epoch, more than the events of any game before the restart
+        let first_event_id = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis() as u64;
+
+        let mut bots = Vec::new();
+        {
+            let mut games = game_states.games.lock().await;
+            let mut bot_games = game_states.bot_games.lock().await;
+            for (id, stored) in stored_games {
+                let game = match GameState::restore(&stored, first_event_id) {
+                    Ok(game) => game,
+                    Err(err) => {
+                        error!("Could not restore game {}: {}", id, err);
+                        continue;
+                    }
+                };
+                if game.phase == GamePhase::Finished {
+                    continue;
+                }
+
+                if stored.info.vs_bot {
+                    bot_games.push(id);
+                    let bot_side = !game.primary_side.clone();
+                    let bot_token = game
+                        .clients
+                        .iter()
+                        .find(|(_, side)| side.as_ref() == Some(&bot_side))
+                        .map(|(token, _)| *token);
+                    if let Some(token) = bot_token {
+                        bots.push((id, token, stored.info.bot_difficulty.clone()));
+                    }
+                }
+                games.insert(id, game);
+            }
+        }
+
+        for (id, token, difficulty) in bots {
+            bot::spawn(game_states.clone(), id, token, difficulty);
+        }
+        Ok(game_states)
+    }
+    pub async fn init_setup(&self, id: Uuid, init_state: &InitState) -> Result<(), InitSetupError> {
+        let mut games = self.games.lock().await;
+        let game = games.get_mut(&id).ok_or(InitSetupError::GameDoesNotExist)?;
+        game.init_setup(init_state)?;
+
+        if let Some(Some(side)) = game.clients.get(&init_state.access_token) {
+            let setup = Setup::new(&game.setups[side], game.rules.layout.width);
+            let side = side.clone();
+            self.storage
+                .write(move |storage| storage.set_setup(id, &side, &setup));
         }
+        Ok(())
+    }
+    pub async fn move_piece(&self, id: Uuid, piece_move: &PieceMove) -> MoveResult {
+        let mut games = self.games.lock().await;
+        let game = games.get_mut(&id).ok_or(MoveError::GameDoesNotExist)?;
+        let res = game.move_piece(piece_move)?;
+
+        let number = game.history.len() - 1;
+        let played = game.history[number].piece_move;
+        self.storage
+            .write(move |storage| storage.add_move(id, number, &played));
+        if let Some(outcome) = game.outcome.clone() {
+            self.storage
+                .write(move |storage| storage.set_outcome(id, &outcome));
+        }
+        Ok(res)
+    }
+    /// Adds `game` to the store, stored with the clients it already has.
+    async fn insert_game(&self, id: Uuid, game_info: &GameInfo, game: GameState) {
+        let game_info = game_info.clone();
+        let clients = game.clients.clone();
+        self.storage.write(move |storage| {
+            storage.create_game(id, &game_info)?;
+            for (token, side) in clients {
+                storage.add_player(id, token, side)?;
+            }
+            Ok(())
+        });
+        self.games.lock().await.insert(id, game);
+    }
+    /// The state of the game `id` as `user_token` sees it.
+    async fn board_state(
+        &self,
+        id: Uuid,
+        user_token: &Uuid,
+    ) -> Result<BoardState, status::Custom<String>> {
+        let games = self.games.lock().await;
+        let game = games.get(&id).ok_or(status::Custom(
+            Status::NotFound,
+            "Game does not exist!".to_owned(),
+        ))?;
+
+        game.board_state_for(user_token).ok_or(status::Custom(
+            Status::Unauthorized,
+            "Not an active user".to_owned(),
+        ))
+    }
+    /// Creates a game played by `rules` with both seats already taken, `side` going to the first
+    /// returned token. Red moves first, whoever arrived last.
+    async fn create_matched_game(
+        &self,
+        side: Side,
+        rules: RuleSet,
+    ) -> (Uuid, UserToken, UserToken) {
+        let id = Uuid::new_v4();
+        let game_info = GameInfo {
+            vs_bot: false,
+            primary_side: Side::Red,
+            spectator_view: SpectatorView::default(),
+            bot_difficulty: Default::default(),
+            rules,
+            training: false,
+        };
+        let mut game = GameState::new(game_info.clone());
+
+        let tokens = [side.clone(), !side].map(|side| {
+            let user_id = Uuid::new_v4();
+            game.clients.insert(user_id, Some(side.clone()));
+            UserToken {
+                access_toket: user_id,
+                side: Some(side),
+            }
+        });
+        game.seats_filled(false);
+
+        self.insert_game(id, &game_info, game).await;
+
+        let [token, other_token] = tokens;
+        (id, token, other_token)
     }
 }
 
 #[post("/create_game", format = "json", data = "<game_info>")]
-async fn create_game(game_states: &State<GameStoreState>, game_info: Json<GameInfo>) -> Json<Uuid> {
+async fn create_game(
+    game_states: &State<GameStoreState>,
+    game_info: Json<GameInfo>,
+) -> Result<Json<Uuid>, status::Custom<String>> {
     let game_info = game_info.0;
+    if !game_info.rules.is_playable() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "Unplayable Rules".to_string(),
+        ));
+    }
 
     let id = Uuid::new_v4();
+    let mut game = GameState::new(game_info.clone());
 
-    game_states
-        .games
-        .lock()
-        .await
-        .insert(id, GameState::new(game_info.primary_side));
     if game_info.vs_bot {
+        let bot_token = Uuid::new_v4();
+        game.clients
+            .insert(bot_token, Some(!game.primary_side.clone()));
         game_states.bot_games.lock().await.push(id);
+        game_states.insert_game(id, &game_info, game).await;
+        bot::spawn(
+            game_states.inner().clone(),
+            id,
+            bot_token,
+            game_info.bot_difficulty,
+        );
+    } else {
+        game_states.insert_game(id, &game_info, game).await;
     }
 
-    id.into()
+    Ok(id.into())
 }
 
 #[get("/<id>/game_exists", format = "json")]
@@ -147,10 +634,15 @@ async fn join_game(
         }
     }
     let user_id = Uuid::new_v4();
-    game.clients.insert(
-        user_id,
-        (join_side.clone(), game_states.changed_games.subscribe()),
-    );
+    game.clients.insert(user_id, join_side.clone());
+    let side = join_side.clone();
+    game_states
+        .storage
+        .write(move |storage| storage.add_player(id, user_id, side));
+    game.seats_filled(bot_games.contains(&id));
+    if let Some(side) = &join_side {
+        game.changed(GameEvent::OpponentJoined(side.clone()));
+    }
 
     Ok(UserToken {
         access_toket: user_id,
@@ -164,56 +656,50 @@ async fn get_game_state(
     game_states: &State<GameStoreState>,
     id: UuidGard,
     user_token: UuidGard,
-) -> Result<Json<BoardState>, status::NotFound<String>> {
-    //TODO! filp for other side
-    let id = id.0;
-    let user_token = user_token.0;
-
-    let games = game_states.games.lock().await;
-    let game = {
-        let game = games.get(&id);
-        game.ok_or(status::NotFound("Game does not exist!".to_owned()))
-    }?;
-
-    let board_state = BoardState {
-        board: game.board.clone(),
-        active_side: game.active_side.clone(),
-        ready: game.ready(),
-    };
+) -> Result<Json<BoardState>, status::Custom<String>> {
+    let board_state = game_states.board_state(id.0, &user_token.0).await?;
 
     Ok(board_state.into())
 }
 
-
-//derecate?
-#[get("/<id>/game_state_changed/<user_token>", format = "json")]
+/// Waits for the game to get past version `since`, giving its state then, or unchanged after
+/// `LONG_POLL_TIMEOUT`.
+#[get("/<id>/game_state_changed/<user_token>?<since>", format = "json")]
 async fn get_game_state_changed(
     game_states: &State<GameStoreState>,
     id: UuidGard,
     user_token: UuidGard,
+    since: u64,
 ) -> Result<Json<BoardState>, status::Custom<String>> {
     let id = id.0;
     let user_token = user_token.0;
 
-    let mut games = game_states.games.lock().await;
-        let game = games.get_mut(&id).ok_or(status::Custom(
+    let mut version = {
+        let games = game_states.games.lock().await;
+        let game = games.get(&id).ok_or(status::Custom(
             Status::NotFound,
             "Game does not exist!".to_owned(),
         ))?;
-    
-        let (_, recv) = game.clients.get_mut(&user_token).ok_or(status::Custom(
-            Status::Unauthorized,
-            "Not an active user".to_owned(),
-        ))?;
-
-    while recv.recv().await.unwrap() != id {}
+        if !game.clients.contains_key(&user_token) {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                "Not an active user".to_owned(),
+            ));
+        }
+        game.version.subscribe()
+    };
 
+    // the store is unlocked while waiting, and the wait ends early if the game is removed
+    let _ = time::timeout(LONG_POLL_TIMEOUT, async {
+        while *version.borrow_and_update() <= since {
+            if version.changed().await.is_err() {
+                break;
+            }
+        }
+    })
+    .await;
 
-    let board_state = BoardState {
-        board: game.board.clone(),
-        active_side: game.active_side.clone(),
-        ready: game.ready(),
-    };
+    let board_state = game_states.board_state(id, &user_token).await?;
 
     Ok(board_state.into())
 }
@@ -227,29 +713,7 @@ async fn move_piece(
     let id = id.0;
     let piece_move = piece_move.0;
 
-    if let Some(game) = game_states.games.lock().await.get_mut(&id) {
-        if let Some((Some(side), _)) = game.clients.get(&piece_move.access_token) {
-            if let Some(_piece) = game.board.0 .0.iter().find(|piece| {
-                if let Some(piece) = piece {
-                    &piece.owner == side && piece.id == piece_move.piece_id
-                } else {
-                    false
-                }
-            }) {
-                game.active_side = !game.active_side.clone();
-                return Json::from(game_logic::move_piece(
-                    &mut game.board,
-                    piece_move.piece_id,
-                    piece_move.x,
-                    piece_move.y,
-                ));
-            }
-        }
-    }
-
-    game_states.changed_games.send(id).unwrap();
-
-    Json::from(Err(MoveError::PieceDoesNotExist(piece_move.piece_id)))
+    Json::from(game_states.move_piece(id, &piece_move).await)
 }
 
 #[post("/<id>/init_setup", format = "json", data = "<init_state>")]
@@ -261,59 +725,155 @@ async fn init_setup(
     let id = id.0;
     let init_state = init_state.0;
 
-    let mut piece_count = HashMap::new();
+    game_states
+        .init_setup(id, &init_state)
+        .await
+        .map_err(|err| status::BadRequest(Json::from(err)))
+}
+
+/// The record of a finished game, as `json` or as Gravon XML with `gravon`. Games finished
+/// before a restart are only in storage.
+#[get("/<id>/record/<format>")]
+async fn export_record(
+    game_states: &State<GameStoreState>,
+    id: UuidGard,
+    format: &str,
+) -> Result<(ContentType, String), status::Custom<String>> {
+    let id = id.0;
+    let not_finished = || status::Custom(Status::Conflict, "Game is not finished".to_owned());
+
+    let in_memory = game_states.games.lock().await.get(&id).map(|game| {
+        if game.phase != GamePhase::Finished {
+            return Err(not_finished());
+        }
+        game.record().ok_or(status::Custom(
+            Status::InternalServerError,
+            "Setups are missing".to_owned(),
+        ))
+    });
+    let record = match in_memory {
+        Some(record) => record?,
+        None => {
+            let stored = game_states
+                .storage
+                .read(move |storage| storage.load_game(id))
+                .await
+                .map_err(|err| status::Custom(Status::InternalServerError, err.to_string()))?
+                .ok_or(status::Custom(
+                    Status::NotFound,
+                    "Game does not exist!".to_owned(),
+                ))?;
+            stored.record().ok_or_else(not_finished)?
+        }
+    };
 
-    for i in 0..40 {
-        let t = &init_state.pieces[i];
-        let c = piece_count.get(&t).unwrap_or(&0);
-        piece_count.insert(t, *c + 1);
+    match format {
+        "json" => Ok((ContentType::JSON, record.to_json())),
+        "gravon" => record
+            .to_gravon()
+            .map(|xml| (ContentType::XML, xml))
+            .map_err(|err| status::Custom(Status::BadRequest, err.to_string())),
+        _ => Err(status::Custom(
+            Status::NotFound,
+            "Unknown record format".to_owned(),
+        )),
     }
+}
+
+#[put("/<id>/resign/<user_token>")]
+async fn resign(
+    game_states: &State<GameStoreState>,
+    id: UuidGard,
+    user_token: UuidGard,
+) -> Result<Json<GameOutcome>, status::Custom<String>> {
+    let id = id.0;
+    let user_token = user_token.0;
 
-    let mut correct_piece_count = true;
+    let mut games = game_states.games.lock().await;
+    let game = games.get_mut(&id).ok_or(status::Custom(
+        Status::NotFound,
+        "Game does not exist!".to_owned(),
+    ))?;
+
+    let side = match game.clients.get(&user_token) {
+        Some(Some(side)) => side.clone(),
+        _ => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                "Not an active player".to_owned(),
+            ))
+        }
+    };
 
-    for piece_type in PieceType::iter() {
-        correct_piece_count &=
-            *piece_count.get(&piece_type).unwrap_or(&0) == piece_type.starting_count();
+    if let Some(outcome) = &game.outcome {
+        return Err(status::Custom(
+            Status::Conflict,
+            format!("Game is already over: {:?}", outcome),
+        ));
     }
 
-    if !correct_piece_count {
-        return Err(status::BadRequest(Some(Json::from(
-            InitSetupError::IncorrectPieceCount,
-        ))))?;
+    let outcome = GameOutcome::Resignation(!side);
+    game.outcome = Some(outcome.clone());
+    game.phase = GamePhase::Finished;
+    game.changed(GameEvent::GameOver(outcome.clone()));
+    let stored = outcome.clone();
+    game_states
+        .storage
+        .write(move |storage| storage.set_outcome(id, &stored));
+
+    Ok(outcome.into())
+}
+
+#[post("/join_random/<side>/<ticket>", format = "json", data = "<rules>")]
+async fn join_random_game(
+    game_states: &State<GameStoreState>,
+    match_queue: &State<MatchQueue>,
+    side: SidePreferenceGard,
+    ticket: UuidGard,
+    rules: Json<RuleSet>,
+) -> Result<Json<(Uuid, UserToken)>, status::Custom<String>> {
+    let side = side.0;
+    let ticket = ticket.0;
+    let rules = rules.0;
+    if !rules.is_playable() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "Unplayable Rules".to_string(),
+        ));
     }
 
-    if let Some(game) = game_states.games.lock().await.get_mut(&id) {
-        if let Some((Some(side), _)) = game.clients.get(&init_state.access_token) {
-            for i in 0..40 {
-                let index = if side == &game.primary_side {
-                    60 + i
-                } else {
-                    39 - i
-                };
-                game.board.0[index] = Some(Piece {
-                    id: Uuid::new_v4(),
-                    owner: side.clone(),
-                    piece_type: init_state.pieces[i].clone(),
-                });
+    let found = loop {
+        match match_queue.enqueue(ticket, side.clone(), rules.clone()).await {
+            Enqueued::Paired(other, side) => {
+                let (id, token, other_token) =
+                    game_states.create_matched_game(side, rules.clone()).await;
+                if other.send((id, other_token)).is_ok() {
+                    break Ok((id, token));
+                }
+                // the other player left between being paired and hearing about it
+                game_states.games.lock().await.remove(&id);
+                game_states
+                    .storage
+                    .write(move |storage| storage.remove_game(id));
             }
-            game_states.changed_games.send(id).unwrap();
-        } else {
-            return Err(status::BadRequest(Some(Json::from(
-                InitSetupError::InvalidAccess,
-            ))))?;
+            Enqueued::Waiting(receiver) => break match_queue.wait(ticket, receiver).await,
         }
-    } else {
-        return Err(status::BadRequest(Some(Json::from(
-            InitSetupError::GameDoesNotExist,
-        ))))?;
+    };
+
+    match found {
+        Ok(found) => Ok(found.into()),
+        Err(MatchError::TimedOut) => Err(status::Custom(
+            Status::RequestTimeout,
+            "No match found".to_owned(),
+        )),
+        Err(MatchError::Cancelled) => Err(status::Custom(
+            Status::Gone,
+            "Matchmaking cancelled".to_owned(),
+        )),
     }
-    Ok(())
 }
 
-#[get("/join_random/<side>", format = "json", rank = 1)]
-async fn join_random_game(
-    game_states: &State<GameStoreState>,
-    side: SideGard,
-) -> Json<(Uuid, UserToken)> {
-    todo!()
+#[delete("/join_random/<ticket>")]
+async fn cancel_random_game(match_queue: &State<MatchQueue>, ticket: UuidGard) -> Json<bool> {
+    match_queue.cancel(ticket.0).await.into()
 }
@@ -0,0 +1,117 @@
+use common::bot::Bot;
+use common::bot::BotDifficulty;
+use common::game_logic::MoveError;
+use common::GamePhase;
+use common::InitState;
+use common::PieceMove;
+use rocket::tokio;
+use uuid::Uuid;
+
+use crate::api::GameStoreState;
+
+/// Plays the bot seat of a `vs_bot` game in the background until the game is over.
+pub fn spawn(game_states: GameStoreState, id: Uuid, token: Uuid, difficulty: BotDifficulty) {
+    tokio::spawn(run(game_states, id, token, difficulty.bot()));
+}
+
+async fn run(game_states: GameStoreState, id: Uuid, token: Uuid, mut bot: Box<dyn Bot>) {
+    let (setup, mut version) = {
+        let games = game_states.games.lock().await;
+        let Some(game) = games.get(&id) else {
+            return;
+        };
+        let Some(Some(side)) = game.clients.get(&token) else {
+            return;
+        };
+        // a game restored from storage may have the bot's setup already
+        let setup = match game.ready.get(side) {
+            Some(true) => None,
+            _ => Some(bot.choose_setup(&game.rules, side)),
+        };
+        (setup, game.version.subscribe())
+    };
+    if let Some(pieces) = setup {
+        let init_state = InitState {
+            access_token: token,
+            pieces,
+            setup: None,
+        };
+        if let Err(err) = game_states.init_setup(id, &init_state).await {
+            error!("Bot of game {} could not set up: {}", id, err);
+            return;
+        }
+    }
+
+    loop {
+        let turn = {
+            let games = game_states.games.lock().await;
+            let Some(game) = games.get(&id) else {
+                return;
+            };
+            let Some(Some(side)) = game.clients.get(&token) else {
+                return;
+            };
+            // seen under the lock, so any later change wakes the bot
+            version.borrow_and_update();
+
+            match game.phase {
+                GamePhase::Finished => return,
+                GamePhase::Playing if &game.active_side == side => Some((
+                    game.board.view_for(Some(side)),
+                    game.rules.clone(),
+                    side.clone(),
+                    game.history.clone(),
+                )),
+                _ => None,
+            }
+        };
+
+        // the lock is released while thinking, the server rejects the move if the game moved on
+        let choice = match turn {
+            Some((view, rules, side, history)) => {
+                let thought = tokio::task::spawn_blocking(move || {
+                    let choice = bot
+                        .choose_move(&view, &rules, &side, &history)
+                        .and_then(|piece_move| {
+                            let piece = view.get(piece_move.from.0, piece_move.from.1)?.as_ref()?;
+                            Some(PieceMove {
+                                access_token: token,
+                                piece_id: piece.id,
+                                x: piece_move.to.0,
+                                y: piece_move.to.1,
+                            })
+                        });
+                    (bot, choice)
+                })
+                .await;
+                let (returned, choice) = match thought {
+                    Ok(thought) => thought,
+                    Err(err) => {
+                        error!("Bot of game {} failed: {}", id, err);
+                        return;
+                    }
+                };
+                bot = returned;
+                choice
+            }
+            None => None,
+        };
+
+        let moved = match choice {
+            Some(piece_move) => match game_states.move_piece(id, &piece_move).await {
+                Err(MoveError::GameDoesNotExist) => return,
+                res => res.is_ok(),
+            },
+            None => false,
+        };
+
+        if moved {
+            continue;
+        }
+
+        // the game was removed if its version is gone
+        if version.changed().await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::time::{Duration, Instant};
+
+use common::event::ClientMessage;
+use common::event::GameEvent;
+use common::PieceMove;
+use rocket::futures::SinkExt;
+use rocket::futures::StreamExt;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::response::stream::Event;
+use rocket::response::stream::EventStream;
+use rocket::serde::json;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::sync::broadcast::Receiver;
+use rocket::Shutdown;
+use rocket::{Route, State};
+use rocket_ws::stream::DuplexStream;
+use rocket_ws::Channel;
+use rocket_ws::Message;
+use rocket_ws::WebSocket;
+use uuid::Uuid;
+
+use crate::api::GameStoreState;
+use crate::api::Viewer;
+use crate::util::LastEventIdGard;
+use crate::util::UuidGard;
+
+/// The longest chat message passed on, in characters.
+const MAX_CHAT_LENGTH: usize = 500;
+/// How long a user waits between chat messages. Messages sent sooner are dropped.
+const CHAT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn channel() -> Vec<Route> {
+    routes![game_channel, game_events]
+}
+
+/// A WebSocket pushing the `GameEvent`s of a game to a client and taking its `ClientMessage`s,
+/// both as JSON text.
+#[get("/<id>/channel/<user_token>")]
+async fn game_channel(
+    ws: WebSocket,
+    game_states: &State<GameStoreState>,
+    id: UuidGard,
+    user_token: UuidGard,
+) -> Result<Channel<'static>, status::Custom<String>> {
+    let id = id.0;
+    let user_token = user_token.0;
+
+    let (viewer, state, events) = {
+        let games = game_states.games.lock().await;
+        let game = games.get(&id).ok_or(status::Custom(
+            Status::NotFound,
+            "Game does not exist!".to_owned(),
+        ))?;
+        let not_active = || status::Custom(Status::Unauthorized, "Not an active user".to_owned());
+        let viewer = game.viewer(&user_token).ok_or_else(not_active)?;
+        let state = game.board_state_for(&user_token).ok_or_else(not_active)?;
+        let state = (game.last_event_id, GameEvent::State(state));
+        (viewer, state, game.events.subscribe())
+    };
+
+    let client = Client {
+        game_states: game_states.inner().clone(),
+        id,
+        user_token,
+        viewer,
+    };
+    Ok(ws.channel(move |stream| Box::pin(client.run(stream, state, events))))
+}
+
+/// The events of `game_channel` as Server-Sent Events with their ids. A client giving the last id
+/// it saw, as `Last-Event-ID` or in the query, gets what it missed since, and otherwise starts
+/// with a `GameEvent::State`.
+#[get("/<id>/events/<user_token>?<last_event_id>")]
+async fn game_events(
+    game_states: &State<GameStoreState>,
+    id: UuidGard,
+    user_token: UuidGard,
+    last_event_id: Option<u64>,
+    resumed: LastEventIdGard,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], status::Custom<String>> {
+    let id = id.0;
+    let user_token = user_token.0;
+
+    let (viewer, missed, mut events) = {
+        let games = game_states.games.lock().await;
+        let game = games.get(&id).ok_or(status::Custom(
+            Status::NotFound,
+            "Game does not exist!".to_owned(),
+        ))?;
+        let not_active = || status::Custom(Status::Unauthorized, "Not an active user".to_owned());
+        let viewer = game.viewer(&user_token).ok_or_else(not_active)?;
+
+        let missed = match resumed.0.or(last_event_id) {
+            Some(last_event_id) => game.events_since(last_event_id),
+            None => None,
+        };
+        // the state stands for every event up to the latest
+        let missed = match missed {
+            Some(missed) => missed,
+            None => {
+                let state = game.board_state_for(&user_token).ok_or_else(not_active)?;
+                vec![(game.last_event_id, GameEvent::State(state))]
+            }
+        };
+        (viewer, missed, game.events.subscribe())
+    };
+
+    let client = Client {
+        game_states: game_states.inner().clone(),
+        id,
+        user_token,
+        viewer,
+    };
+    Ok(EventStream! {
+        let mut last = 0;
+        for (id, event) in missed {
+            last = id;
+            yield sse_event(id, &client.viewer.event(event));
+        }
+
+        loop {
+            let (id, event) = select! {
+                event = events.recv() => match event {
+                    Ok((id, event)) => (id, client.viewer.event(event)),
+                    Err(RecvError::Lagged(_)) => match client.state().await {
+                        Some(state) => state,
+                        None => break,
+                    },
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            // already covered by the state that started the stream
+            if id <= last {
+                continue;
+            }
+            last = id;
+            yield sse_event(id, &event);
+        }
+    })
+}
+
+fn sse_event(id: u64, event: &GameEvent) -> Event {
+    Event::json(event).id(id.to_string())
+}
+
+struct Client {
+    game_states: GameStoreState,
+    id: Uuid,
+    user_token: Uuid,
+    viewer: Viewer,
+}
+
+impl Client {
+    /// Sends `state`, with the id of the latest event it covers, and then every later event.
+    async fn run(
+        self,
+        mut stream: DuplexStream,
+        (mut last, state): (u64, GameEvent),
+        mut events: Receiver<(u64, GameEvent)>,
+    ) -> rocket_ws::result::Result<()> {
+        send(&mut stream, &state).await?;
+
+        loop {
+            select! {
+                event = events.recv() => {
+                    let (id, event) = match event {
+                        Ok((id, event)) => (id, self.viewer.event(event)),
+                        // too far behind to catch up event by event, start over from the state
+                        Err(RecvError::Lagged(_)) => match self.state().await {
+                            Some(state) => state,
+                            None => break,
+                        },
+                        Err(RecvError::Closed) => break,
+                    };
+                    // already covered by a state that was sent
+                    if id <= last {
+                        continue;
+                    }
+                    last = id;
+                    send(&mut stream, &event).await?;
+                }
+                message = stream.next() => {
+                    let text = match message {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => return Err(err),
+                    };
+                    // anything else is not from one of our clients, so it is ignored
+                    if let Ok(message) = json::from_str(&text) {
+                        if let Some(reply) = self.handle(message).await {
+                            send(&mut stream, &reply).await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The state of the game, with the id of the latest event it covers.
+    async fn state(&self) -> Option<(u64, GameEvent)> {
+        let games = self.game_states.games.lock().await;
+        let game = games.get(&self.id)?;
+        let state = game.board_state_for(&self.user_token)?;
+        Some((game.last_event_id, GameEvent::State(state)))
+    }
+
+    /// Acts on `message`, giving what only this client is told about it.
+    async fn handle(&self, message: ClientMessage) -> Option<GameEvent> {
+        match message {
+            ClientMessage::Move { piece_id, x, y } => {
+                let piece_move = PieceMove {
+                    access_token: self.user_token,
+                    piece_id,
+                    x,
+                    y,
+                };
+                let res = self.game_states.move_piece(self.id, &piece_move).await;
+                res.err().map(GameEvent::MoveRejected)
+            }
+            ClientMessage::Chat(text) => {
+                let mut games = self.game_states.games.lock().await;
+                let game = games.get_mut(&self.id)?;
+                // kept per user, so opening more connections does not get around it
+                let too_soon = match game.last_chat.get(&self.user_token) {
+                    Some(last_chat) => last_chat.elapsed() < CHAT_INTERVAL,
+                    None => false,
+                };
+                if too_soon || text.chars().count() > MAX_CHAT_LENGTH {
+                    return None;
+                }
+                game.last_chat.insert(self.user_token, Instant::now());
+                game.emit(GameEvent::Chat {
+                    from: self.viewer.side.clone(),
+                    text,
+                });
+                None
+            }
+        }
+    }
+}
+
+async fn send(stream: &mut DuplexStream, event: &GameEvent) -> rocket_ws::result::Result<()> {
+    let text = json::to_string(event).expect("events always serialize");
+    stream.send(Message::Text(text)).await
+}
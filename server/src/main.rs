@@ -1,19 +1,25 @@
-use api::GameStoreState;
+use matchmaking::MatchQueue;
 use rocket::fs::{FileServer, Options};
 
 #[macro_use]
 extern crate rocket;
 
 mod api;
+mod bot;
+mod channel;
+mod matchmaking;
+mod storage;
 mod util;
 mod web_app;
 
 #[launch]
 fn rocket() -> _ {
     rocket::build()
-        .manage(GameStoreState::default())
+        .attach(storage::stage())
+        .manage(MatchQueue::default())
         .mount("/", web_app::web_app())
         .mount("/api", api::api())
+        .mount("/api", channel::channel())
         .mount(
             "/static",
             FileServer::new("../web/dist", Options::None).rank(2),
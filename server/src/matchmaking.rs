@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use common::rules::RuleSet;
+use common::Side;
+use common::SidePreference;
+use common::UserToken;
+use rocket::tokio::sync::oneshot;
+use rocket::tokio::sync::oneshot::error::TryRecvError;
+use rocket::tokio::sync::Mutex;
+use rocket::tokio::time;
+use uuid::Uuid;
+
+pub type MatchFound = (Uuid, UserToken);
+
+pub struct Ticket {
+    pub id: Uuid,
+    pub preference: SidePreference,
+    /// The rules the player wants to play by.
+    pub rules: RuleSet,
+    sender: oneshot::Sender<MatchFound>,
+}
+
+impl Ticket {
+    /// Hands the created game to the waiting player, giving it back if they are gone.
+    pub fn send(self, found: MatchFound) -> Result<(), MatchFound> {
+        self.sender.send(found)
+    }
+}
+
+/// Decides who a newly queued player gets paired with.
+pub trait Pairing: Send + Sync {
+    /// The index of the waiting ticket to pair `ticket` with and the side `ticket` plays.
+    fn pair(&self, waiting: &[Ticket], ticket: &Ticket) -> Option<(usize, Side)>;
+}
+
+/// Pairs with whoever has waited longest, wants a compatible side and the same rules.
+pub struct FirstCompatible;
+
+impl Pairing for FirstCompatible {
+    fn pair(&self, waiting: &[Ticket], ticket: &Ticket) -> Option<(usize, Side)> {
+        waiting.iter().enumerate().find_map(|(i, other)| {
+            if other.rules != ticket.rules {
+                return None;
+            }
+            [Side::Red, Side::Blue]
+                .into_iter()
+                .find(|side| ticket.preference.accepts(side) && other.preference.accepts(&!side))
+                .map(|side| (i, side))
+        })
+    }
+}
+
+pub enum Enqueued {
+    Paired(Ticket, Side),
+    Waiting(oneshot::Receiver<MatchFound>),
+}
+
+#[derive(Debug)]
+pub enum MatchError {
+    TimedOut,
+    Cancelled,
+}
+
+pub struct MatchQueue {
+    waiting: Mutex<Vec<Ticket>>,
+    pairing: Box<dyn Pairing>,
+    timeout: Duration,
+}
+
+impl MatchQueue {
+    pub fn new(pairing: impl Pairing + 'static, timeout: Duration) -> Self {
+        Self {
+            waiting: Default::default(),
+            pairing: Box::new(pairing),
+            timeout,
+        }
+    }
+
+    /// Pairs the player with a waiting one if possible, otherwise queues them.
+    pub async fn enqueue(&self, id: Uuid, preference: SidePreference, rules: RuleSet) -> Enqueued {
+        let (sender, receiver) = oneshot::channel();
+        let ticket = Ticket {
+            id,
+            preference,
+            rules,
+            sender,
+        };
+
+        let mut waiting = self.waiting.lock().await;
+        waiting.retain(|other| other.id != id);
+
+        if let Some((i, side)) = self.pairing.pair(&waiting, &ticket) {
+            Enqueued::Paired(waiting.remove(i), side)
+        } else {
+            waiting.push(ticket);
+            Enqueued::Waiting(receiver)
+        }
+    }
+
+    pub async fn wait(
+        &self,
+        id: Uuid,
+        mut receiver: oneshot::Receiver<MatchFound>,
+    ) -> Result<MatchFound, MatchError> {
+        match time::timeout(self.timeout, &mut receiver).await {
+            Ok(Ok(found)) => Ok(found),
+            Ok(Err(_)) => Err(MatchError::Cancelled),
+            Err(_) => {
+                // a match may have been made while timing out, so only give up if still queued
+                if self.cancel(id).await {
+                    return Err(MatchError::TimedOut);
+                }
+                match receiver.try_recv() {
+                    Ok(found) => Ok(found),
+                    Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => {
+                        Err(MatchError::Cancelled)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes a ticket from the queue, returning whether it was still waiting.
+    pub async fn cancel(&self, id: Uuid) -> bool {
+        let mut waiting = self.waiting.lock().await;
+        let len = waiting.len();
+        waiting.retain(|ticket| ticket.id != id);
+        waiting.len() != len
+    }
+}
+
+impl Default for MatchQueue {
+    fn default() -> Self {
+        Self::new(FirstCompatible, Duration::from_secs(120))
+    }
+}
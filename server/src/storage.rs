@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use common::game_logic::GameOutcome;
+use common::game_logic::Move;
+use common::record::GameRecord;
+use common::setup::Setup;
+use common::GameInfo;
+use common::Side;
+use rocket::fairing::AdHoc;
+use rocket::serde::json;
+use rocket::tokio::sync::mpsc;
+use rocket::tokio::sync::oneshot;
+use rusqlite::params;
+use rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::api::GameStoreState;
+
+/// What is kept of a game, enough to play it back to where it was.
+#[derive(Clone)]
+pub struct StoredGame {
+    pub info: GameInfo,
+    /// The token and side of every client, bots included.
+    pub players: Vec<(Uuid, Option<Side>)>,
+    pub setups: HashMap<Side, Setup>,
+    pub moves: Vec<Move>,
+    /// Needed for outcomes the moves do not lead to, like a resignation.
+    pub outcome: Option<GameOutcome>,
+}
+
+impl StoredGame {
+    fn new(info: GameInfo) -> Self {
+        Self {
+            info,
+            players: Vec::new(),
+            setups: HashMap::new(),
+            moves: Vec::new(),
+            outcome: None,
+        }
+    }
+
+    /// The record of the game, `None` until it is finished.
+    pub fn record(&self) -> Option<GameRecord> {
+        Some(GameRecord {
+            rules: self.info.rules.clone(),
+            primary_side: self.info.primary_side.clone(),
+            red_setup: self.setups.get(&Side::Red)?.clone(),
+            blue_setup: self.setups.get(&Side::Blue)?.clone(),
+            moves: self.moves.clone(),
+            outcome: Some(self.outcome.clone()?),
+        })
+    }
+}
+
+/// Where games are kept so they outlive the server. Every change to a game is stored as it
+/// happens.
+pub trait GameStorage: Send + Sync {
+    /// Every game kept, to carry on with after a restart.
+    fn load(&self) -> anyhow::Result<HashMap<Uuid, StoredGame>>;
+    /// The game kept as `id`, if there is one.
+    fn load_game(&self, id: Uuid) -> anyhow::Result<Option<StoredGame>>;
+    fn create_game(&self, id: Uuid, info: &GameInfo) -> anyhow::Result<()>;
+    fn add_player(&self, id: Uuid, token: Uuid, side: Option<Side>) -> anyhow::Result<()>;
+    fn set_setup(&self, id: Uuid, side: &Side, setup: &Setup) -> anyhow::Result<()>;
+    /// Stores the move played as the `number`th of the game, counting from 0.
+    fn add_move(&self, id: Uuid, number: usize, piece_move: &Move) -> anyhow::Result<()>;
+    fn set_outcome(&self, id: Uuid, outcome: &GameOutcome) -> anyhow::Result<()>;
+    fn remove_game(&self, id: Uuid) -> anyhow::Result<()>;
+}
+
+/// A change for a `StorageWriter` to make.
+type Write = Box<dyn FnOnce(&dyn GameStorage) -> anyhow::Result<()> + Send>;
+
+/// Makes changes to a `GameStorage` on a thread of its own, so neither the async executor nor
+/// the games lock wait on it. Changes are made in the order they were handed over. Failures are
+/// only logged, the game carrying on in memory. Reads go through the same thread, so they see
+/// every change handed over before them.
+#[derive(Clone)]
+pub struct StorageWriter {
+    writes: mpsc::UnboundedSender<Write>,
+}
+
+impl StorageWriter {
+    pub fn spawn(storage: Arc<dyn GameStorage>) -> Self {
+        let (writes, mut received) = mpsc::unbounded_channel::<Write>();
+        std::thread::spawn(move || {
+            while let Some(write) = received.blocking_recv() {
+                if let Err(err) = write(storage.as_ref()) {
+                    error!("Could not store a game change: {}", err);
+                }
+            }
+        });
+        Self { writes }
+    }
+
+    pub fn write(
+        &self,
+        write: impl FnOnce(&dyn GameStorage) -> anyhow::Result<()> + Send + 'static,
+    ) {
+        if self.writes.send(Box::new(write)).is_err() {
+            error!("Could not store a game change: the writer stopped");
+        }
+    }
+
+    pub async fn read<T: Send + 'static>(
+        &self,
+        read: impl FnOnce(&dyn GameStorage) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let (sender, receiver) = oneshot::channel();
+        self.writes
+            .send(Box::new(move |storage| {
+                // the reader may have stopped waiting
+                let _ = sender.send(read(storage));
+                Ok(())
+            }))
+            .map_err(|_| anyhow::anyhow!("the writer stopped"))?;
+        receiver.await?
+    }
+}
+
+/// Keeps games only as long as the server runs.
+#[derive(Default)]
+pub struct MemoryStorage {
+    games: Mutex<HashMap<Uuid, StoredGame>>,
+}
+
+impl MemoryStorage {
+    fn update(&self, id: Uuid, update: impl FnOnce(&mut StoredGame)) -> anyhow::Result<()> {
+        let mut games = self.games.lock().unwrap();
+        let game = games
+            .get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Game {} is not stored", id))?;
+        update(game);
+        Ok(())
+    }
+}
+
+impl GameStorage for MemoryStorage {
+    fn load(&self) -> anyhow::Result<HashMap<Uuid, StoredGame>> {
+        Ok(self.games.lock().unwrap().clone())
+    }
+
+    fn load_game(&self, id: Uuid) -> anyhow::Result<Option<StoredGame>> {
+        Ok(self.games.lock().unwrap().get(&id).cloned())
+    }
+
+    fn create_game(&self, id: Uuid, info: &GameInfo) -> anyhow::Result<()> {
+        let game = StoredGame::new(info.clone());
+        self.games.lock().unwrap().insert(id, game);
+        Ok(())
+    }
+
+    fn add_player(&self, id: Uuid, token: Uuid, side: Option<Side>) -> anyhow::Result<()> {
+        self.update(id, |game| game.players.push((token, side)))
+    }
+
+    fn set_setup(&self, id: Uuid, side: &Side, setup: &Setup) -> anyhow::Result<()> {
+        self.update(id, |game| {
+            game.setups.insert(side.clone(), setup.clone());
+        })
+    }
+
+    fn add_move(&self, id: Uuid, number: usize, piece_move: &Move) -> anyhow::Result<()> {
+        self.update(id, |game| {
+            game.moves.truncate(number);
+            game.moves.push(*piece_move);
+        })
+    }
+
+    fn set_outcome(&self, id: Uuid, outcome: &GameOutcome) -> anyhow::Result<()> {
+        self.update(id, |game| game.outcome = Some(outcome.clone()))
+    }
+
+    fn remove_game(&self, id: Uuid) -> anyhow::Result<()> {
+        self.games.lock().unwrap().remove(&id);
+        Ok(())
+    }
+}
+
+/// Keeps games in an SQLite database, one table each for games, players, setups and moves.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it and its tables if they are missing.
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE IF NOT EXISTS games (
+                id TEXT PRIMARY KEY,
+                info TEXT NOT NULL,
+                outcome TEXT
+            );
+            CREATE TABLE IF NOT EXISTS players (
+                token TEXT PRIMARY KEY,
+                game_id TEXT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+                side TEXT
+            );
+            CREATE TABLE IF NOT EXISTS setups (
+                game_id TEXT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+                side TEXT NOT NULL,
+                setup TEXT NOT NULL,
+                PRIMARY KEY (game_id, side)
+            );
+            CREATE TABLE IF NOT EXISTS moves (
+                game_id TEXT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+                number INTEGER NOT NULL,
+                from_x INTEGER NOT NULL,
+                from_y INTEGER NOT NULL,
+                to_x INTEGER NOT NULL,
+                to_y INTEGER NOT NULL,
+                PRIMARY KEY (game_id, number)
+            );",
+        )?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn execute(&self, sql: &str, params: impl rusqlite::Params) -> anyhow::Result<()> {
+        self.connection.lock().unwrap().execute(sql, params)?;
+        Ok(())
+    }
+
+    /// The game kept as `id`, or every game kept if it is `None`.
+    fn select(&self, id: Option<Uuid>) -> anyhow::Result<HashMap<Uuid, StoredGame>> {
+        let connection = self.connection.lock().unwrap();
+        let id = id.map(|id| id.to_string());
+        let mut games = HashMap::new();
+
+        let mut statement = connection
+            .prepare("SELECT id, info, outcome FROM games WHERE ?1 IS NULL OR id = ?1")?;
+        let mut rows = statement.query(params![id])?;
+        while let Some(row) = rows.next()? {
+            let info: GameInfo = json::from_str(&row.get::<_, String>(1)?)?;
+            let mut game = StoredGame::new(info);
+            if let Some(outcome) = row.get::<_, Option<String>>(2)? {
+                game.outcome = Some(json::from_str(&outcome)?);
+            }
+            games.insert(parse_uuid(row.get(0)?)?, game);
+        }
+
+        let mut statement = connection
+            .prepare("SELECT game_id, token, side FROM players WHERE ?1 IS NULL OR game_id = ?1")?;
+        let mut rows = statement.query(params![id])?;
+        while let Some(row) = rows.next()? {
+            let Some(game) = games.get_mut(&parse_uuid(row.get(0)?)?) else {
+                continue;
+            };
+            let side = row
+                .get::<_, Option<String>>(2)?
+                .map(parse_side)
+                .transpose()?;
+            game.players.push((parse_uuid(row.get(1)?)?, side));
+        }
+
+        let mut statement = connection
+            .prepare("SELECT game_id, side, setup FROM setups WHERE ?1 IS NULL OR game_id = ?1")?;
+        let mut rows = statement.query(params![id])?;
+        while let Some(row) = rows.next()? {
+            let Some(game) = games.get_mut(&parse_uuid(row.get(0)?)?) else {
+                continue;
+            };
+            let setup = row.get::<_, String>(2)?.parse::<Setup>()?;
+            game.setups.insert(parse_side(row.get(1)?)?, setup);
+        }
+
+        let mut statement = connection.prepare(
+            "SELECT game_id, from_x, from_y, to_x, to_y FROM moves WHERE ?1 IS NULL OR game_id = ?1
+            ORDER BY game_id, number",
+        )?;
+        let mut rows = statement.query(params![id])?;
+        while let Some(row) = rows.next()? {
+            let Some(game) = games.get_mut(&parse_uuid(row.get(0)?)?) else {
+                continue;
+            };
+            game.moves.push(Move {
+                from: (row.get(1)?, row.get(2)?),
+                to: (row.get(3)?, row.get(4)?),
+            });
+        }
+
+        Ok(games)
+    }
+}
+
+fn parse_uuid(text: String) -> anyhow::Result<Uuid> {
+    Ok(Uuid::from_str(&text)?)
+}
+
+fn parse_side(text: String) -> anyhow::Result<Side> {
+    Ok(Side::from_str(&text)?)
+}
+
+impl GameStorage for SqliteStorage {
+    fn load(&self) -> anyhow::Result<HashMap<Uuid, StoredGame>> {
+        self.select(None)
+    }
+
+    fn load_game(&self, id: Uuid) -> anyhow::Result<Option<StoredGame>> {
+        Ok(self.select(Some(id))?.remove(&id))
+    }
+
+    fn create_game(&self, id: Uuid, info: &GameInfo) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT INTO games (id, info) VALUES (?1, ?2)",
+            params![id.to_string(), json::to_string(info)?],
+        )
+    }
+
+    fn add_player(&self, id: Uuid, token: Uuid, side: Option<Side>) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT INTO players (token, game_id, side) VALUES (?1, ?2, ?3)",
+            params![
+                token.to_string(),
+                id.to_string(),
+                side.map(|side| side.to_string())
+            ],
+        )
+    }
+
+    fn set_setup(&self, id: Uuid, side: &Side, setup: &Setup) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO setups (game_id, side, setup) VALUES (?1, ?2, ?3)",
+            params![id.to_string(), side.to_string(), setup.to_string()],
+        )
+    }
+
+    fn add_move(&self, id: Uuid, number: usize, piece_move: &Move) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO moves (game_id, number, from_x, from_y, to_x, to_y)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                id.to_string(),
+                number,
+                piece_move.from.0,
+                piece_move.from.1,
+                piece_move.to.0,
+                piece_move.to.1
+            ],
+        )
+    }
+
+    fn set_outcome(&self, id: Uuid, outcome: &GameOutcome) -> anyhow::Result<()> {
+        self.execute(
+            "UPDATE games SET outcome = ?2 WHERE id = ?1",
+            params![id.to_string(), json::to_string(outcome)?],
+        )
+    }
+
+    fn remove_game(&self, id: Uuid) -> anyhow::Result<()> {
+        self.execute("DELETE FROM games WHERE id = ?1", params![id.to_string()])
+    }
+}
+
+/// Manages a `GameStoreState` holding every game kept in the SQLite database at the
+/// `games_database` config value, or one kept in memory if it is not set.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Game Storage", |rocket| async {
+        let storage: Arc<dyn GameStorage> =
+            match rocket.figment().extract_inner::<String>("games_database") {
+                Ok(path) => match SqliteStorage::open(&path) {
+                    Ok(storage) => Arc::new(storage),
+                    Err(err) => {
+                        error!("Could not open the games database {}: {}", path, err);
+                        return Err(rocket);
+                    }
+                },
+                Err(_) => Arc::new(MemoryStorage::default()),
+            };
+
+        match GameStoreState::load(storage).await {
+            Ok(game_states) => Ok(rocket.manage(game_states)),
+            Err(err) => {
+                error!("Could not load the stored games: {}", err);
+                Err(rocket)
+            }
+        }
+    })
+}
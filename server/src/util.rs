@@ -1,7 +1,11 @@
+use std::convert::Infallible;
 use std::str::FromStr;
 
-use common::Side;
+use common::SidePreference;
 use rocket::request::FromParam;
+use rocket::request::FromRequest;
+use rocket::request::Outcome;
+use rocket::Request;
 use uuid::Uuid;
 
 pub struct UuidGard(pub Uuid);
@@ -17,15 +21,31 @@ impl<'a> FromParam<'a> for UuidGard {
     }
 }
 
-pub struct SideGard(pub Side);
+pub struct SidePreferenceGard(pub SidePreference);
 
-impl<'a> FromParam<'a> for SideGard {
+impl<'a> FromParam<'a> for SidePreferenceGard {
     type Error = &'a str;
 
     fn from_param(param: &'a str) -> Result<Self, Self::Error> {
-        match Side::from_str(param) {
-            Ok(side) => Ok(SideGard(side)),
+        match SidePreference::from_str(param) {
+            Ok(side) => Ok(SidePreferenceGard(side)),
             Err(_) => Err(param),
         }
     }
 }
+
+/// The `Last-Event-ID` header an event stream client sends when it reconnects.
+pub struct LastEventIdGard(pub Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventIdGard {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let id = request
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|id| id.parse().ok());
+        Outcome::Success(LastEventIdGard(id))
+    }
+}
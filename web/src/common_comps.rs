@@ -15,6 +15,11 @@ pub enum Route {
     NotFound,
 }
 
+/// Session storage key of the `UserToken` held for a game, so a reload keeps the same seat.
+pub fn token_key(id: Uuid) -> String {
+    format!("user_token/{}", id)
+}
+
 #[function_component(Acount)]
 pub fn acount() -> Html {
     html! {
@@ -1,15 +1,22 @@
+use std::cell::Cell;
 use std::collections::HashMap;
-use std::time::Duration;
-
-use crate::game::game_logic::MoveResult;
-use common::game_logic::{self, MoveError};
-use common::utils::SendibleArray;
-use common::{request, Board, BoardState, PieceMove, PieceType, Side, BOARD_SIZE};
-use common::{InitState, UserToken};
-use strum::IntoEnumIterator;
+use std::rc::Rc;
+
+use crate::common_comps::token_key;
+use common::event::{ClientMessage, GameEvent};
+use common::game_logic::{self, GameOutcome, PlayedMove};
+use common::inference;
+use common::request::ChannelSender;
+use common::rules::RuleSet;
+use common::setup::Setup;
+use common::{request, Board, GamePhase, PieceType, Side};
+use common::{InitSetupError, InitState, UserToken};
+use gloo::storage::{SessionStorage, Storage};
+use gloo::timers::future::TimeoutFuture;
 use uuid::Uuid;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::UnwrapThrowExt;
+use web_sys::HtmlInputElement;
 use yew::prelude::*;
 use yew::suspense::Suspension;
 use yew::suspense::SuspensionResult;
@@ -26,9 +33,14 @@ fn use_join_game(id: Uuid) -> SuspensionResult<Result<UserToken, String>> {
         let token_state = token_state.clone();
         use_state(|| {
             Suspension::from_future(async move {
-                token_state.set(Some(
-                    request::join_game(id).await.map_err(|e| e.to_string()),
-                ));
+                let token = match SessionStorage::get(token_key(id)) {
+                    Ok(token) => Ok(token),
+                    Err(_) => request::join_game(id).await.map_err(|e| e.to_string()),
+                };
+                if let Ok(token) = &token {
+                    SessionStorage::set(token_key(id), token).unwrap();
+                }
+                token_state.set(Some(token));
             })
         })
     };
@@ -89,37 +101,36 @@ pub fn game_loader(props: &Props) -> HtmlResult {
         })
     }
 }
-fn get_changed_board(ctx: &Context<Game>) {
-    let game_id = ctx.props().id.clone();
-    let user_id = ctx.props().access_toket.clone();
+/// How long to wait before reopening a game channel that closed, in milliseconds.
+const RECONNECT_DELAY: u32 = 2000;
+
+/// Opens the game channel, its events arriving as `GameMsg::Event` and its closing, or failing
+/// to open, as `GameMsg::Closed`.
+fn open_channel(ctx: &Context<Game>) -> Option<ChannelSender> {
+    let link = ctx.link().clone();
+    let (sender, mut receiver) =
+        match request::open_channel(ctx.props().id, ctx.props().access_toket) {
+            Ok(channel) => channel,
+            Err(err) => {
+                log::info!("{}", err);
+                link.send_message(GameMsg::Closed);
+                return None;
+            }
+        };
 
-    ctx.link().send_future(async move {
-        async_std::task::sleep(Duration::from_secs(2)).await;
-        GameMsg::UpdateBoard(request::get_game_state(game_id, user_id).await.unwrap())
-    });
-    /*
-    ctx.link().send_future(async move {
-        loop {
-            let changed = request::get_game_state_changed(game_id, user_id)
-                .await
-                .unwrap();
-            if changed {
-                let board = request::get_game_state(game_id, user_id).await.unwrap();
-                return GameMsg::UpdateBoard(board);
+    wasm_bindgen_futures::spawn_local(async move {
+        while let Some(event) = receiver.next().await {
+            match event {
+                Ok(event) => link.send_message(GameMsg::Event(event)),
+                Err(err) => log::info!("{}", err),
             }
-            async_std::task::sleep(Duration::from_secs(2)).await;
         }
+        link.send_message(GameMsg::Closed);
     });
-    */
-}
 
-fn get_board(ctx: &Context<Game>) {
-    let game_id = ctx.props().id.clone();
-    let user_id = ctx.props().access_toket.clone();
-    ctx.link().send_future(async move {
-        GameMsg::UpdateBoard(request::get_game_state(game_id, user_id).await.unwrap())
-    });
+    Some(sender)
 }
+
 #[derive(Properties, PartialEq)]
 pub struct GameProps {
     pub id: Uuid,
@@ -128,52 +139,52 @@ pub struct GameProps {
 }
 
 enum GameMsg {
-    UpdateBoard(BoardState),
+    Event(GameEvent),
+    /// The channel closed. It is opened again after `RECONNECT_DELAY`, starting over from a
+    /// `GameEvent::State`.
+    Closed,
+    Reconnect,
     ClearSelect,
     Select(usize, usize),
-    PieceMoved(MoveResult),
+    SendChat(String),
+    Resign,
+    Resigned(GameOutcome),
+    ToggleAssistant,
 }
 
 struct Game {
     board: Board,
     active_side: Side,
+    phase: GamePhase,
+    outcome: Option<GameOutcome>,
+    history: Vec<PlayedMove>,
+    rules: RuleSet,
+    training: bool,
+    assistant: bool,
     selected: Option<(usize, usize)>,
     highlighted: HashMap<(usize, usize), bool>,
+    channel: Option<ChannelSender>,
+    chat: Vec<(Option<Side>, String)>,
 }
 
-
 impl Game {
-    fn move_piece(&mut self, ctx: &Context<Game>, x: usize, y: usize) {
+    fn move_piece(&mut self, x: usize, y: usize) {
         if let Some(selected) = self.selected {
-            log::info!("Move Result: {:?}", game_logic::valid_move(&self.board, selected.0, selected.1, x, y));
-            if game_logic::valid_move(&self.board, selected.0, selected.1, x, y)
+            log::info!("Move Result: {:?}", game_logic::valid_move(&self.board, &self.rules, selected.0, selected.1, x, y));
+            if game_logic::valid_move(&self.board, &self.rules, selected.0, selected.1, x, y)
                 .is_ok()
             {
-                {
-                    let game_id = ctx.props().id;
-                    let access_toket = ctx.props().access_toket;
-                    let id = self
+                if let Some(channel) = &self.channel {
+                    let piece_id = self
                         .board
                         .get(selected.0, selected.1)
                         .unwrap()
                         .clone()
                         .unwrap()
                         .id;
-                    ctx.link().send_future(async move {
-                        GameMsg::PieceMoved(
-                            request::move_piece(
-                                game_id,
-                                PieceMove {
-                                    access_token: access_toket,
-                                    piece_id: id,
-                                    x,
-                                    y,
-                                },
-                            )
-                            .await
-                            .unwrap(),
-                        )
-                    });
+                    if let Err(err) = channel.send(ClientMessage::Move { piece_id, x, y }) {
+                        log::info!("{}", err);
+                    }
                 }
 
                 self.selected = None;
@@ -189,23 +200,62 @@ impl Component for Game {
 
     fn create(ctx: &Context<Self>) -> Self {
         Self {
-            board: Board::new(),
+            board: Board::default(),
             active_side: Side::Red,
+            phase: GamePhase::WaitingForPlayers,
+            outcome: None,
+            history: Vec::new(),
+            rules: RuleSet::default(),
+            training: false,
+            assistant: false,
             selected: None,
             highlighted: HashMap::new(),
+            channel: open_channel(ctx),
+            chat: Vec::new(),
         }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         if let Some(msg) = msg {
             match msg {
-                GameMsg::UpdateBoard(board) => {
-                    if !board.ready || board.active_side != ctx.props().side {
-                        get_changed_board(ctx);
+                GameMsg::Event(event) => match event {
+                    GameEvent::State(board) => {
+                        self.board = board.board;
+                        self.active_side = board.active_side;
+                        self.phase = board.phase;
+                        self.outcome = board.outcome;
+                        self.history = board.history;
+                        self.rules = board.rules;
+                        self.training = board.training;
                     }
-                    self.board = board.board;
-                    self.active_side = board.active_side;
-                    
+                    GameEvent::OpponentJoined(_) => {}
+                    GameEvent::SetupDone { board, phase, .. } => {
+                        self.board = board;
+                        self.phase = phase;
+                    }
+                    GameEvent::MoveMade { played, board } => {
+                        self.board = board;
+                        self.active_side = !played.side.clone();
+                        self.history.push(played);
+                    }
+                    GameEvent::GameOver(outcome) => {
+                        self.outcome = Some(outcome);
+                        self.phase = GamePhase::Finished;
+                        self.selected = None;
+                        self.highlighted.clear();
+                    }
+                    GameEvent::Chat { from, text } => self.chat.push((from, text)),
+                    GameEvent::MoveRejected(err) => log::info!("{}", err),
+                },
+                GameMsg::Closed => {
+                    self.channel = None;
+                    ctx.link().send_future(async {
+                        TimeoutFuture::new(RECONNECT_DELAY).await;
+                        Some(GameMsg::Reconnect)
+                    });
+                }
+                GameMsg::Reconnect => {
+                    self.channel = open_channel(ctx);
                 }
                 GameMsg::ClearSelect => {
                     self.selected = None;
@@ -213,28 +263,57 @@ impl Component for Game {
                 }
                 GameMsg::Select(x, y) => {
                     log::info!("Select: ({}, {})", x, y);
-                    if self.active_side == ctx.props().side {
+                    if self.phase == GamePhase::Playing && self.active_side == ctx.props().side {
                         if let Some(Some(piece)) = self.board.get(x, y) {
                             if piece.owner == ctx.props().side {
                                 self.selected = Some((x, y));
                                 self.highlighted.clear();
-                                for i in 0..BOARD_SIZE {
-                                    let u = i % 10;
-                                    let v = i / 10;
-                                    let mov = game_logic::valid_move(&self.board, x, y, u, v);
-                                    self.highlighted.insert((u, v), mov.is_ok());
+                                for piece_move in game_logic::legal_moves_from(&self.board, &self.rules, x, y) {
+                                    let allowed = game_logic::check_repetition(
+                                        &self.board,
+                                        &self.rules,
+                                        &self.history,
+                                        &ctx.props().side,
+                                        &piece_move,
+                                    );
+                                    self.highlighted.insert(piece_move.to, allowed.is_ok());
                                 }
                             } else {
-                                self.move_piece(ctx, x, y);
+                                self.move_piece(x, y);
                             }
                         } else {
-                            self.move_piece(ctx, x, y);
+                            self.move_piece(x, y);
+                        }
+                    }
+                }
+                GameMsg::SendChat(text) => {
+                    if let Some(channel) = &self.channel {
+                        if let Err(err) = channel.send(ClientMessage::Chat(text)) {
+                            log::info!("{}", err);
                         }
                     }
                 }
-                GameMsg::PieceMoved(_res) => {
-                    get_board(ctx);
-                    get_changed_board(ctx);
+                GameMsg::Resign => {
+                    let game_id = ctx.props().id;
+                    let user_id = ctx.props().access_toket;
+                    ctx.link().send_future(async move {
+                        match request::resign(game_id, user_id).await {
+                            Ok(outcome) => Some(GameMsg::Resigned(outcome)),
+                            Err(err) => {
+                                log::info!("{}", err);
+                                None
+                            }
+                        }
+                    });
+                }
+                GameMsg::ToggleAssistant => {
+                    self.assistant = !self.assistant;
+                }
+                GameMsg::Resigned(outcome) => {
+                    self.outcome = Some(outcome);
+                    self.phase = GamePhase::Finished;
+                    self.selected = None;
+                    self.highlighted.clear();
                 }
             }
 
@@ -255,10 +334,33 @@ impl Component for Game {
             }
         });
 
+        let send_chat = ctx.link().callback(|e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            let text = input.value();
+            input.set_value("");
+            (!text.is_empty()).then_some(GameMsg::SendChat(text))
+        });
+        let resign = ctx.link().callback(|_| Some(GameMsg::Resign));
+        let toggle_assistant = ctx.link().callback(|_| Some(GameMsg::ToggleAssistant));
+
+        let beliefs = if self.training && self.assistant {
+            inference::infer(&self.board, &self.rules, &ctx.props().side, &self.history)
+        } else {
+            HashMap::new()
+        };
+
         html! {
             <game {onkeydown}>
             {
-                if self.active_side.clone() != ctx.props().side {
+                if let Some(outcome) = &self.outcome {
+                    html! {
+                        <game_over>{outcome_text(outcome, &ctx.props().side)}</game_over>
+                    }
+                } else if self.phase != GamePhase::Playing {
+                    html! {
+                        <waiting>{"Waiting for opponent"}</waiting>
+                    }
+                } else if self.active_side.clone() != ctx.props().side {
                     html! {
                         <waiting>{format!("Waiting for {}", self.active_side)}</waiting>
                     }
@@ -266,19 +368,57 @@ impl Component for Game {
                     html! { }
                 }
             }
-                <BoardComponent on_click={callback} board={self.board.clone()} selected={self.selected} highlighted={self.highlighted.clone()} />
+                <BoardComponent on_click={callback} board={self.board.clone()} selected={self.selected} highlighted={self.highlighted.clone()} {beliefs} pieces={self.rules.pieces.clone()} />
+                <chat>
+                {
+                    for self.chat.iter().map(|(from, text)| {
+                        let from = from.as_ref().map_or("Spectator".to_owned(), |side| side.to_string());
+                        html! { <message>{format!("{}: {}", from, text)}</message> }
+                    })
+                }
+                    <input type={"text"} onchange={send_chat}/>
+                </chat>
+            {
+                if self.outcome.is_none() {
+                    html! {
+                        <finish>
+                            <button onclick={resign}>{"Resign"}</button>
+                            {
+                                if self.training {
+                                    html! {
+                                        <button onclick={toggle_assistant}>
+                                            {if self.assistant { "Hide Assistant" } else { "Assistant" }}
+                                        </button>
+                                    }
+                                } else {
+                                    html! { }
+                                }
+                            }
+                        </finish>
+                    }
+                } else {
+                    html! { }
+                }
+            }
             </game>
         }
     }
+}
 
-    fn rendered(&mut self, ctx: &Context<Self>, first_render: bool) {
-        
-        if first_render {
-            get_board(ctx);
-        }
-
-    }
-
+fn outcome_text(outcome: &GameOutcome, side: &Side) -> String {
+    let result = match outcome.winner() {
+        Some(winner) if winner == side => "You Won",
+        Some(_) => "You Lost",
+        None => "Draw",
+    };
+    let reason = match outcome {
+        GameOutcome::FlagCaptured(_) => "flag captured",
+        GameOutcome::NoMovablePieces(_) => "no movable pieces",
+        GameOutcome::Draw => return "Draw".to_owned(),
+        GameOutcome::Resignation(_) => "resignation",
+        GameOutcome::Timeout(_) => "timeout",
+    };
+    format!("{} ({})", result, reason)
 }
 
 #[derive(Properties, PartialEq)]
@@ -290,7 +430,8 @@ struct GameViewerProps {
 #[function_component(GameViewer)]
 fn game_viewer(props: &GameViewerProps) -> Html {
     // needs upgrade to struct component
-    let board_state = use_state(|| common::Board::new());
+    let board_state = use_state(common::Board::default);
+    let pieces_state = use_state(Vec::new);
 
     let callback = Callback::from(move |e| {
         log::info!("game viewer: {:?}", e);
@@ -300,23 +441,40 @@ fn game_viewer(props: &GameViewerProps) -> Html {
         let game_id = props.game_id.clone();
         let user_id = props.access_token.access_toket;
         let board_state = board_state.clone();
+        let pieces_state = pieces_state.clone();
 
         use_effect_with_deps(
             move |_| {
-                wasm_bindgen_futures::spawn_local(async move {
-                    let board = request::get_game_state(game_id, user_id).await.unwrap();
-
-                    board_state.set(board.board);
-
-                    while !request::get_game_state_changed(game_id, user_id)
-                        .await
-                        .unwrap()
-                    {
-                        async_std::task::sleep(Duration::from_secs(2)).await;
-                    }
-                    let board = request::get_game_state(game_id, user_id).await.unwrap();
-                    board_state.set(board.board);
-                });
+                let shown = Rc::new(Cell::new(true));
+                // spectators only listen, opening the channel again whenever it closes
+                {
+                    let shown = shown.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        while shown.get() {
+                            match request::open_channel(game_id, user_id) {
+                                Ok((_, mut receiver)) => {
+                                    while let Some(event) = receiver.next().await {
+                                        match event {
+                                            Ok(GameEvent::State(board)) => {
+                                                pieces_state.set(board.rules.pieces);
+                                                board_state.set(board.board);
+                                            }
+                                            Ok(GameEvent::SetupDone { board, .. })
+                                            | Ok(GameEvent::MoveMade { board, .. }) => {
+                                                board_state.set(board)
+                                            }
+                                            Ok(_) => {}
+                                            Err(err) => log::info!("{}", err),
+                                        }
+                                    }
+                                }
+                                Err(err) => log::info!("{}", err),
+                            }
+                            TimeoutFuture::new(RECONNECT_DELAY).await;
+                        }
+                    });
+                }
+                move || shown.set(false)
             },
             (),
         );
@@ -324,7 +482,7 @@ fn game_viewer(props: &GameViewerProps) -> Html {
 
     html! {
         <game>
-            <BoardComponent on_click={callback} board={(*board_state).clone()}/>
+            <BoardComponent on_click={callback} board={(*board_state).clone()} pieces={(*pieces_state).clone()}/>
         </game>
     }
 }
@@ -338,9 +496,28 @@ struct SetupGameProps {
 
 #[function_component(SetupGame)]
 fn setup_game(props: &SetupGameProps) -> Html {
-    let board_state = use_state(|| common::Board::new());
+    let board_state = use_state(common::Board::default);
+    let rules_state = use_state(RuleSet::default);
     let selected_piece_state = use_state(|| Option::<PieceType>::None);
 
+    {
+        let game_id = props.game_id;
+        let user_id = props.access_token.access_toket;
+        let board_state = board_state.clone();
+        let rules_state = rules_state.clone();
+
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    let board = request::get_game_state(game_id, user_id).await.unwrap();
+                    board_state.set(common::Board::new(board.rules.layout.clone()));
+                    rules_state.set(board.rules);
+                });
+            },
+            (),
+        );
+    }
+
     {
         let selected_piece_state = selected_piece_state.clone();
         use_effect(move || {
@@ -368,17 +545,18 @@ fn setup_game(props: &SetupGameProps) -> Html {
         let board_state = board_state.clone();
         let side = props.access_token.side.clone().unwrap();
         let selected_piece_state = selected_piece_state.clone();
+        let rules = (*rules_state).clone();
 
         Callback::from(move |e| {
             let (x, y, event): (usize, usize, MouseEvent) = e;
             let mut board = (*board_state).clone();
             event.prevent_default();
 
-            if y >= 6 {
+            if rules.layout.setup_zone(true).contains(&(x, y)) {
                 if event.button() == 0 {
                     if let Some(piece) = &(*selected_piece_state) {
                         let count = (*board_state).count();
-                        if count[&piece] < piece.starting_count() {
+                        if count.get(piece).unwrap_or(&0) < &rules.piece_count(piece) {
                             board.set(
                                 x,
                                 y,
@@ -386,6 +564,7 @@ fn setup_game(props: &SetupGameProps) -> Html {
                                     id: Uuid::new_v4(),
                                     owner: side.clone(),
                                     piece_type: piece.clone(),
+                                    knowledge: Default::default(),
                                 }),
                             );
                         }
@@ -398,30 +577,83 @@ fn setup_game(props: &SetupGameProps) -> Html {
         })
     };
 
-    let mut count = (*board_state).count();
-    let mut finishable = true;
+    let placed = (*board_state).count();
+    let count = rules_state
+        .pieces
+        .iter()
+        .map(|piece| {
+            let left = piece.count - placed.get(&piece.piece_type).unwrap_or(&0);
+            (piece.piece_type.clone(), left)
+        })
+        .collect::<HashMap<_, _>>();
+    let finishable = count.values().all(|count| *count == 0);
+
+    let pieces = rules_state
+        .layout
+        .setup_zone(true)
+        .iter()
+        .map(|&(x, y)| {
+            let piece = board_state.get(x, y).cloned().flatten();
+            piece.map(|piece| piece.piece_type)
+        })
+        .collect::<Vec<_>>();
+    let setup = Setup::new(&pieces, rules_state.layout.width);
 
-    for piece_type in PieceType::iter() {
-        count.insert(
-            piece_type.clone(),
-            piece_type.starting_count() - count[&piece_type],
-        );
-        finishable &= *count.get(&piece_type).unwrap_or(&0) == 0;
-    }
+    let paste_callback = {
+        let board_state = board_state.clone();
+        let side = props.access_token.side.clone().unwrap();
+        let rules = (*rules_state).clone();
+        Callback::from(move |e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            let setup = match input.value().parse::<Setup>() {
+                Ok(setup) if setup.rows.iter().all(|row| row.len() == rules.layout.width) => {
+                    setup.pieces()
+                }
+                Ok(_) => {
+                    log::info!("setup rows do not fit the board");
+                    return;
+                }
+                Err(err) => {
+                    log::info!("{}", err);
+                    return;
+                }
+            };
+
+            let zone = rules.layout.setup_zone(true);
+            let mut count = HashMap::new();
+            for piece_type in setup.iter().flatten() {
+                *count.entry(piece_type).or_insert(0) += 1;
+            }
+            if setup.len() != zone.len()
+                || count.iter().any(|(piece_type, count)| *count > rules.piece_count(piece_type))
+            {
+                log::info!("setup does not fit the rules");
+                return;
+            }
+
+            let mut board = common::Board::new(rules.layout.clone());
+            for (&(x, y), piece_type) in zone.iter().zip(setup) {
+                let piece = piece_type.map(|piece_type| common::Piece {
+                    id: Uuid::new_v4(),
+                    owner: side.clone(),
+                    piece_type,
+                    knowledge: Default::default(),
+                });
+                board.set(x, y, piece);
+            }
+            board_state.set(board);
+        })
+    };
 
     let finsh_callback = {
-        let board_state = board_state.clone();
         let access_token = props.access_token.access_toket.clone();
         let game_id = props.game_id.clone();
         let setup_callback = props.setup_callback.clone();
         Callback::from(move |_| {
-            let mut pieces = SendibleArray::<PieceType, 40>::default();
-            for i in 0..40 {
-                pieces[i] = (*board_state).0[i + 60].clone().unwrap().piece_type;
-            }
             let init_state = InitState {
                 access_token: access_token,
-                pieces,
+                pieces: pieces.clone(),
+                setup: None,
             };
             let setup_callback = setup_callback.clone();
             wasm_bindgen_futures::spawn_local(async move {
@@ -430,7 +662,7 @@ fn setup_game(props: &SetupGameProps) -> Html {
                         setup_callback.emit(());
                     }
                     Err(err) => {
-                        let err: MoveError = err.downcast().unwrap();
+                        let err: InitSetupError = err.downcast().unwrap();
                         log::info!("{}", err);
                     }
                 }
@@ -440,8 +672,11 @@ fn setup_game(props: &SetupGameProps) -> Html {
 
     html! {
         <game>
-            <BoardComponent on_click={board_callback} board={(*board_state).clone()}/>
-            <SetupBar side={props.access_token.side.clone().unwrap()} type_select={bar_callback} selected_type={(*selected_piece_state).clone()} type_count={count}/>
+            <BoardComponent on_click={board_callback} board={(*board_state).clone()} pieces={rules_state.pieces.clone()}/>
+            <SetupBar side={props.access_token.side.clone().unwrap()} type_select={bar_callback} selected_type={(*selected_piece_state).clone()} type_count={count} pieces={rules_state.pieces.clone()}/>
+            <setup_text>
+                <input type={"text"} value={setup.to_string()} onchange={paste_callback}/>
+            </setup_text>
             {
                 if finishable {
                     html!{
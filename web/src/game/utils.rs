@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 
-use common::{Side, PieceType};
-use strum::IntoEnumIterator;
+use common::catalog::PieceDefinition;
+use common::inference::{self, Distribution};
+use common::layout::BoardLayout;
+use common::{PieceKnowledge, PieceType, Side};
+use uuid::Uuid;
 use web_sys::MouseEvent;
 use yew::{Properties, Callback, function_component, Html, html, Classes, classes};
 
@@ -12,15 +15,43 @@ pub struct BoardProps {
     pub selected: Option<(usize, usize)>,
     #[prop_or_default]
     pub highlighted: HashMap<(usize, usize), bool>,
+    /// Guesses at hidden pieces to show on top of them, empty when the assistant is off.
+    #[prop_or_default]
+    pub beliefs: HashMap<Uuid, Distribution>,
+    /// The catalog of the rules, the classic pieces are drawn without it.
+    #[prop_or_default]
+    pub pieces: Vec<PieceDefinition>,
+}
+
+/// The picture to draw for `piece_type`.
+fn asset(pieces: &[PieceDefinition], piece_type: &PieceType) -> String {
+    match pieces.iter().find(|piece| &piece.piece_type == piece_type) {
+        Some(piece) => piece.asset.clone(),
+        None => piece_type.to_string().to_lowercase(),
+    }
+}
+
+fn name(pieces: &[PieceDefinition], piece_type: &PieceType) -> String {
+    match pieces.iter().find(|piece| &piece.piece_type == piece_type) {
+        Some(piece) => piece.name.clone(),
+        None => piece_type.to_string(),
+    }
 }
 
 #[function_component(BoardComponent)]
 pub fn board(props: &BoardProps) -> Html {
+    let layout = &props.board.layout;
     let mut pieces = Vec::new();
 
-    for i in 0..100 {
-        let x = i % 10;
-        let y = i / 10;
+    for i in 0..layout.size() {
+        let (x, y) = layout.square(i);
+
+        if layout.is_impassable(x, y) {
+            pieces.push(html! {
+                <lake style={format!("grid-column: {}; grid-row: {};", x + 1, y + 1)} />
+            });
+            continue;
+        }
 
         let callback = {
             let on_click = props.on_click.clone();
@@ -28,15 +59,21 @@ pub fn board(props: &BoardProps) -> Html {
                 on_click.emit((x, y, e));
             })
         };
-        if let Some(piece) = &props.board.0[i] {
+        if let Some(piece) = &props.board.squares[i] {
             let selected = if let Some((u, v)) = props.selected {
                 x == u && y == v
             } else {
                 false
             };
             let highlighted = *props.highlighted.get(&(x, y)).unwrap_or(&false);
+            let belief = props
+                .beliefs
+                .get(&piece.id)
+                .and_then(inference::most_likely)
+                .map(|(piece_type, chance)| (name(&props.pieces, piece_type), *chance));
+            let asset = asset(&props.pieces, &piece.piece_type);
             pieces.push(html! {
-                <Piece side={piece.owner.clone()} piece_type={piece.piece_type.clone()} {x} {y} on_click={callback} {selected} {highlighted} />
+                <Piece side={piece.owner.clone()} piece_type={piece.piece_type.clone()} {asset} knowledge={piece.knowledge.clone()} {x} {y} on_click={callback} {selected} {highlighted} {belief} />
             });
         } else {
             let mut class = Classes::new();
@@ -56,8 +93,17 @@ pub fn board(props: &BoardProps) -> Html {
         }
     }
 
+    // the background picture only fits the classic board
+    let classic = BoardLayout::classic();
+    let class = if (layout.width, layout.height, &layout.impassable) != (classic.width, classic.height, &classic.impassable) {
+        classes!("plain")
+    } else {
+        classes!()
+    };
+    let style = format!("aspect-ratio: {} / {};", layout.width, layout.height);
+
     html! {
-        <board>
+        <board {class} {style}>
             {pieces}
         </board>
     }
@@ -67,6 +113,9 @@ pub fn board(props: &BoardProps) -> Html {
 pub struct PieceProps {
     pub side: Side,
     pub piece_type: PieceType,
+    pub asset: String,
+    #[prop_or_default]
+    pub knowledge: PieceKnowledge,
     pub x: usize,
     pub y: usize,
     pub on_click: Callback<MouseEvent>,
@@ -74,6 +123,9 @@ pub struct PieceProps {
     pub selected: bool,
     #[prop_or_default]
     pub highlighted: bool,
+    /// The most likely type of a hidden piece by name, and its chance.
+    #[prop_or_default]
+    pub belief: Option<(String, f32)>,
 }
 
 #[function_component(Piece)]
@@ -85,10 +137,37 @@ pub fn piece(props: &PieceProps) -> Html {
     if props.highlighted {
         class.push("highlighted");
     }
+    if props.knowledge.revealed {
+        class.push("revealed");
+    }
+    if props.knowledge.moved {
+        class.push("moved");
+    }
+
+    let style = format!("grid-column: {}; grid-row: {};", props.x + 1, props.y + 1);
+
+    if props.piece_type == PieceType::Unknown {
+        class.push(props.side.to_string());
+        return html! {
+            <piece class={class} {style}>
+                <hidden onclick={props.on_click.clone()} oncontextmenu={props.on_click.clone()}>
+                {
+                    if let Some((name, chance)) = &props.belief {
+                        html! {
+                            <belief>{format!("{} {:.0}%", name, chance * 100.0)}</belief>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+                </hidden>
+            </piece>
+        };
+    }
 
     html! {
         <piece class={class}>
-            <img onclick={props.on_click.clone()} oncontextmenu={props.on_click.clone()} style={format!("grid-column: {}; grid-row: {};", props.x + 1, props.y + 1)} src={format!("/static/assets/temp/{} {}.webp", props.side.to_string(), props.piece_type.to_string().to_lowercase())}/>
+            <img onclick={props.on_click.clone()} oncontextmenu={props.on_click.clone()} {style} src={format!("/static/assets/temp/{} {}.webp", props.side.to_string(), props.asset)}/>
         </piece>
     }
 }
@@ -98,47 +177,50 @@ pub struct SetupBarProps {
     pub side: Side,
     pub selected_type: Option<PieceType>,
     pub type_select: Callback<PieceType>,
+    /// How many of each type are left to place.
     pub type_count: HashMap<PieceType, usize>,
+    /// The catalog of the rules, in the order the types are offered.
+    pub pieces: Vec<PieceDefinition>,
 }
 
 #[function_component(SetupBar)]
 pub fn setup_bar(props: &SetupBarProps) -> Html {
     let mut pieces = Vec::new();
-    for piece_type in PieceType::iter() {
-        if piece_type != PieceType::Unknown {
-            let callback = {
-                let type_select = props.type_select.clone();
+    for piece in &props.pieces {
+        let piece_type = piece.piece_type.clone();
+        let count = props.type_count.get(&piece_type).unwrap_or(&0);
+        let callback = {
+            let type_select = props.type_select.clone();
+            let piece_type = piece_type.clone();
+            Callback::from(move |_| {
                 let piece_type = piece_type.clone();
-                Callback::from(move |_| {
-                    let piece_type = piece_type.clone();
-                    type_select.emit(piece_type);
-                })
-            };
+                type_select.emit(piece_type);
+            })
+        };
 
-            let class = if Some(piece_type.clone()) == props.selected_type {
-                classes!("selected")
-            } else {
-                classes!()
-            };
+        let class = if Some(piece_type.clone()) == props.selected_type {
+            classes!("selected")
+        } else {
+            classes!()
+        };
 
-            pieces.push(html! {
-                <piece_box onclick={callback} class={class}>
-                    <piece>
-                        <img src={format!("/static/assets/temp/{} {}.webp", props.side.to_string(), piece_type.to_string().to_lowercase())}/>
-                    </piece>
-                    
-                    <text>
-                        <name>
-                            {piece_type.to_string()}
-                        </name>
-                        <spacer/>
-                        <count>
-                            {props.type_count[&piece_type]}
-                        </count>
-                    </text>
-                </piece_box>
-            });
-        }
+        pieces.push(html! {
+            <piece_box onclick={callback} class={class}>
+                <piece>
+                    <img src={format!("/static/assets/temp/{} {}.webp", props.side.to_string(), piece.asset)}/>
+                </piece>
+                
+                <text>
+                    <name>
+                        {piece.name.clone()}
+                    </name>
+                    <spacer/>
+                    <count>
+                        {count}
+                    </count>
+                </text>
+            </piece_box>
+        });
     }
 
     html! {
@@ -1,10 +1,14 @@
-use common::{request, GameInfo, Side};
+use common::bot::BotDifficulty;
+use common::rules::Variant;
+use common::{request, GameInfo, Side, SidePreference, SpectatorView};
+use strum::IntoEnumIterator;
+use gloo::storage::{SessionStorage, Storage};
 use uuid::Uuid;
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
 use yew_router::prelude::*;
 
-use crate::common_comps::Route;
+use crate::common_comps::{token_key, Route};
 
 #[derive(PartialEq, Clone, Debug)]
 pub enum MenuState {
@@ -12,9 +16,12 @@ pub enum MenuState {
     FriendSelect,
     TeamSelect(GameType),
     JoinSelect,
-    GameRandom(Side),
-    GameComputer(Side),
-    NewGameFriend(Side),
+    GameRandom(SidePreference, Uuid),
+    VariantSelect(Side, GameType),
+    DifficultySelect(Side, Variant),
+    /// The last field is whether the game is for training, see `GameInfo::training`.
+    GameComputer(Side, BotDifficulty, Variant, bool),
+    NewGameFriend(Side, Variant),
     JoinGameFriend(Uuid),
 }
 #[derive(PartialEq, Clone, Debug)]
@@ -48,24 +55,29 @@ pub fn select_game() -> Html {
         MenuState::JoinSelect => html! {
             <JoinSelect {change_state}/>
         },
-        MenuState::GameRandom(side) => {
-            let side = side.clone();
-            wasm_bindgen_futures::spawn_local(async move {
-                let navigator = navigator.clone();
-                let game_id = request::join_random_game(side).await.unwrap();
-                navigator.push(&Route::Game { id: game_id });
-            });
-            html! {
-                <Wait game_type={GameType::Random} />
-            }
-        }
-        MenuState::GameComputer(side) => {
+        MenuState::VariantSelect(side, game_type) => html! {
+            <VariantSelect {change_state} side={side.clone()} game_type={game_type.clone()}/>
+        },
+        MenuState::DifficultySelect(side, variant) => html! {
+            <DifficultySelect {change_state} side={side.clone()} variant={variant.clone()}/>
+        },
+        MenuState::GameRandom(side, ticket) => html! {
+            <RandomGame {change_state} side={side.clone()} ticket={*ticket}/>
+        },
+        MenuState::GameComputer(side, difficulty, variant, training) => {
             let side = side.clone();
+            let difficulty = difficulty.clone();
+            let rules = variant.rules();
+            let training = *training;
             wasm_bindgen_futures::spawn_local(async move {
                 let navigator = navigator.clone();
                 let game_id = request::create_game(GameInfo {
                     vs_bot: true,
                     primary_side: side,
+                    spectator_view: SpectatorView::Hidden,
+                    bot_difficulty: difficulty,
+                    rules,
+                    training,
                 })
                 .await
                 .unwrap();
@@ -75,13 +87,18 @@ pub fn select_game() -> Html {
                 <Wait game_type={GameType::Computer} />
             }
         }
-        MenuState::NewGameFriend(side) => {
+        MenuState::NewGameFriend(side, variant) => {
             let side = side.clone();
+            let rules = variant.rules();
             wasm_bindgen_futures::spawn_local(async move {
                 let navigator = navigator.clone();
                 let gameid = request::create_game(GameInfo {
                     vs_bot: false,
                     primary_side: side,
+                    spectator_view: SpectatorView::Hidden,
+                    bot_difficulty: BotDifficulty::default(),
+                    rules,
+                    training: false,
                 })
                 .await
                 .unwrap();
@@ -136,20 +153,40 @@ pub struct TeamProps {
 #[function_component(TeamSelect)]
 fn team_select(props: &TeamProps) -> Html {
     let (red, blue) = match props.game_type {
-        GameType::Computer => (
-            change_state_on_click(MenuState::GameComputer(Side::Red), &props.change_state),
-            change_state_on_click(MenuState::GameComputer(Side::Blue), &props.change_state),
-        ),
-        GameType::Friend => (
-            change_state_on_click(MenuState::NewGameFriend(Side::Red), &props.change_state),
-            change_state_on_click(MenuState::NewGameFriend(Side::Blue), &props.change_state),
+        GameType::Computer | GameType::Friend => (
+            change_state_on_click(
+                MenuState::VariantSelect(Side::Red, props.game_type.clone()),
+                &props.change_state,
+            ),
+            change_state_on_click(
+                MenuState::VariantSelect(Side::Blue, props.game_type.clone()),
+                &props.change_state,
+            ),
         ),
         GameType::Random => (
-            change_state_on_click(MenuState::GameRandom(Side::Red), &props.change_state),
-            change_state_on_click(MenuState::GameRandom(Side::Blue), &props.change_state),
+            change_state_on_click(
+                MenuState::GameRandom(SidePreference::Red, Uuid::new_v4()),
+                &props.change_state,
+            ),
+            change_state_on_click(
+                MenuState::GameRandom(SidePreference::Blue, Uuid::new_v4()),
+                &props.change_state,
+            ),
         ),
     };
 
+    let either = if props.game_type == GameType::Random {
+        let either = change_state_on_click(
+            MenuState::GameRandom(SidePreference::Either, Uuid::new_v4()),
+            &props.change_state,
+        );
+        html! {
+            <button onclick={either}>{"Either"}</button>
+        }
+    } else {
+        html! {}
+    };
+
     let back_state = match props.game_type {
         GameType::Random => MenuState::GameSelect,
         GameType::Friend => MenuState::FriendSelect,
@@ -163,7 +200,87 @@ fn team_select(props: &TeamProps) -> Html {
             <button_row>
                 <button onclick={red} class="red">{"Red"}</button>
                 <button onclick={blue} class="blue">{"Blue"}</button>
+                {either}
+            </button_row>
+        </select_game>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct VariantProps {
+    pub change_state: Callback<MenuState>,
+    pub side: Side,
+    pub game_type: GameType,
+}
+
+#[function_component(VariantSelect)]
+fn variant_select(props: &VariantProps) -> Html {
+    let buttons = Variant::iter()
+        .map(|variant| {
+            let state = match props.game_type {
+                GameType::Computer => MenuState::DifficultySelect(props.side.clone(), variant.clone()),
+                _ => MenuState::NewGameFriend(props.side.clone(), variant.clone()),
+            };
+            let onclick = change_state_on_click(state, &props.change_state);
+            html! {
+                <button {onclick}>{variant.to_string()}</button>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <select_game>
+            <Back change_state={props.change_state.clone()} prev_menu_state={MenuState::TeamSelect(props.game_type.clone())}/>
+            <h1>{"Pick a Variant"}</h1>
+            <button_row>
+                {buttons}
+            </button_row>
+        </select_game>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct DifficultyProps {
+    pub change_state: Callback<MenuState>,
+    pub side: Side,
+    pub variant: Variant,
+}
+
+#[function_component(DifficultySelect)]
+fn difficulty_select(props: &DifficultyProps) -> Html {
+    let training = use_state(|| false);
+
+    let buttons = BotDifficulty::iter()
+        .map(|difficulty| {
+            let onclick = change_state_on_click(
+                MenuState::GameComputer(
+                    props.side.clone(),
+                    difficulty.clone(),
+                    props.variant.clone(),
+                    *training,
+                ),
+                &props.change_state,
+            );
+            html! {
+                <button {onclick}>{difficulty.to_string()}</button>
+            }
+        })
+        .collect::<Html>();
+    let toggle_training = {
+        let training = training.clone();
+        Callback::from(move |_| training.set(!*training))
+    };
+
+    html! {
+        <select_game>
+            <Back change_state={props.change_state.clone()} prev_menu_state={MenuState::VariantSelect(props.side.clone(), GameType::Computer)}/>
+            <h1>{"Pick a Difficulty"}</h1>
+            <button_row>
+                {buttons}
             </button_row>
+            <button onclick={toggle_training}>
+                {if *training { "Training: On" } else { "Training: Off" }}
+            </button>
         </select_game>
     }
 }
@@ -238,10 +355,60 @@ fn join_select(props: &Props) -> Html {
     }
 }
 
+#[derive(Properties, PartialEq)]
+pub struct RandomGameProps {
+    pub change_state: Callback<MenuState>,
+    pub side: SidePreference,
+    pub ticket: Uuid,
+}
+
+/// Waits in the matchmaking queue with `ticket` until a game is found.
+#[function_component(RandomGame)]
+fn random_game(props: &RandomGameProps) -> Html {
+    let navigator = use_navigator().unwrap();
+    let ticket = props.ticket;
+
+    {
+        let side = props.side.clone();
+        let change_state = props.change_state.clone();
+        // joining again with the same ticket would drop the request already waiting
+        use_effect_with_deps(
+            move |&ticket| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match request::join_random_game(side, ticket, &Variant::Classic.rules()).await {
+                        Ok((game_id, user_token)) => {
+                            SessionStorage::set(token_key(game_id), user_token).unwrap();
+                            navigator.push(&Route::Game { id: game_id });
+                        }
+                        Err(err) => {
+                            log::info!("{}", err);
+                            change_state.emit(MenuState::GameSelect);
+                        }
+                    }
+                });
+            },
+            ticket,
+        );
+    }
+
+    let on_cancel = Callback::from(move |_| {
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(err) = request::cancel_random_game(ticket).await {
+                log::info!("{}", err);
+            }
+        });
+    });
+    html! {
+        <Wait game_type={GameType::Random} {on_cancel} />
+    }
+}
+
 #[derive(Properties, PartialEq)]
 pub struct WaitProps {
     pub game_type: GameType,
     pub joining: Option<bool>,
+    #[prop_or_default]
+    pub on_cancel: Option<Callback<MouseEvent>>,
 }
 
 #[function_component(Wait)]
@@ -262,9 +429,18 @@ fn wait(props: &WaitProps) -> Html {
         GameType::Random => "Waiting For Match To Be Made",
     };
 
+    let cancel = if let Some(on_cancel) = &props.on_cancel {
+        html! {
+            <button onclick={on_cancel.clone()}>{"Cancel"}</button>
+        }
+    } else {
+        html! {}
+    };
+
     html! {
         <select_game>
             <h1>{text}</h1>
+            {cancel}
         </select_game>
     }
 }
@@ -286,7 +462,7 @@ fn back(props: &BackProps) -> Html {
 1. game_select -> 1. freind_select | 2. team_select(rand) | 3. team_select(comp)
 2. freind_select -> 1. team_select(friend) | 2. join_select
 3. team_select(rand) -> wait(rand)
-4. team_select(comp) -> game
-5. team_select(friend) -> wait(friend)
+4. team_select(comp) -> variant_select -> difficulty_select -> game
+5. team_select(friend) -> variant_select -> wait(friend)
 6. join_select -> game
 */